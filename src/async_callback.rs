@@ -0,0 +1,240 @@
+use crate::callback::{CallbackRegistry, DEFAULT_PRIORITY};
+use crate::combiner::Collect;
+use crate::subscription::Subscription;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// The future returned by the async callbacks.
+///
+/// It is boxed cause every closure returns a different type of future.
+pub type CallbackFuture = Pin<Box<dyn Future<Output=()>>>;
+
+/// AsyncCallbackRegistry tracks callbacks returning futures and awaits them when requested.
+///
+/// It is a `CallbackRegistry` returning `CallbackFuture`, so it has the same rules for priorities
+/// and subscriptions.
+/// No runtime is used, the futures returned by the `run_all_*` methods can be awaited by any
+/// executor.
+pub struct AsyncCallbackRegistry<ParamType> {
+	registry: CallbackRegistry<ParamType, CallbackFuture>,
+}
+
+/// Creates a registry with no closures, for any type of parameter.
+impl<ParamType> Default for AsyncCallbackRegistry<ParamType> {
+	fn default() -> Self {
+		Self {
+			registry: Default::default(),
+		}
+	}
+}
+
+impl<ParamType: Clone> AsyncCallbackRegistry<ParamType> {
+	/// The `add` method accepts a closure returning a future and returns a `Subscription`.
+	/// Once the `Subscription` is dropped the closure will not be executed anymore.
+	#[track_caller]
+	pub fn add(&self, callback: Box<dyn FnMut(ParamType) -> CallbackFuture>) -> Subscription {
+		self.add_with_priority(callback, DEFAULT_PRIORITY)
+	}
+
+	/// The `add_with_priority` method works like `add`, see
+	/// `CallbackRegistry::add_with_priority`.
+	#[track_caller]
+	pub fn add_with_priority(
+		&self, callback: Box<dyn FnMut(ParamType) -> CallbackFuture>, priority: i32,
+	) -> Subscription {
+		self.registry.add_with_priority(callback, priority)
+	}
+}
+
+impl<ParamType> AsyncCallbackRegistry<ParamType> {
+	/// The `run_all_async` method calls all the closures and awaits their futures one after the
+	/// other, in the order of the closures.
+	///
+	/// The closures are called right away, the returned future does not borrow the registry.
+	pub fn run_all_async(&self, param: ParamType) -> impl Future<Output=()> {
+		let futures = self.registry.run_all_with(param, Collect);
+		async move {
+			for future in futures {
+				future.await;
+			}
+		}
+	}
+
+	/// The `run_all_concurrent` method calls all the closures and awaits their futures
+	/// concurrently, it finishes once all of them are finished.
+	///
+	/// The closures are called right away, the returned future does not borrow the registry.
+	pub fn run_all_concurrent(&self, param: ParamType) -> JoinAll {
+		JoinAll {
+			futures: self.registry.run_all_with(param, Collect).into_iter().map(Some).collect(),
+		}
+	}
+}
+
+/// Future returned by `AsyncCallbackRegistry::run_all_concurrent`.
+///
+/// Every time it is polled it polls all the futures that are not finished yet.
+pub struct JoinAll {
+	futures: Vec<Option<CallbackFuture>>,
+}
+
+impl Future for JoinAll {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let mut finished = true;
+		for it in self.futures.iter_mut() {
+			if let Some(future) = it {
+				if future.as_mut().poll(cx).is_ready() {
+					*it = None;
+				} else {
+					finished = false;
+				}
+			}
+		}
+		if finished {
+			Poll::Ready(())
+		} else {
+			Poll::Pending
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::async_callback::AsyncCallbackRegistry;
+	use std::cell::RefCell;
+	use std::future::Future;
+	use std::pin::Pin;
+	use std::rc::Rc;
+	use std::sync::Arc;
+	use std::task::{Context, Poll, Wake, Waker};
+
+	/// Waker which does nothing, the tests keep polling until the future is ready.
+	struct NoopWaker;
+
+	impl Wake for NoopWaker {
+		fn wake(self: Arc<Self>) {}
+	}
+
+	/// Minimal executor for the tests.
+	fn block_on<F: Future>(future: F) -> F::Output {
+		let waker = Waker::from(Arc::new(NoopWaker));
+		let mut cx = Context::from_waker(&waker);
+		let mut future = Box::pin(future);
+		loop {
+			if let Poll::Ready(resp) = future.as_mut().poll(&mut cx) {
+				return resp;
+			}
+		}
+	}
+
+	/// Future which is pending on the first poll, so other futures can make progress.
+	#[derive(Default)]
+	struct YieldNow {
+		yielded: bool,
+	}
+
+	impl Future for YieldNow {
+		type Output = ();
+
+		fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+			if self.yielded {
+				Poll::Ready(())
+			} else {
+				self.yielded = true;
+				cx.waker().wake_by_ref();
+				Poll::Pending
+			}
+		}
+	}
+
+	#[test]
+	fn sequential_dispatch() {
+		let my_callback: AsyncCallbackRegistry<u32> = Default::default();
+		let steps = Rc::new(RefCell::new(Vec::new()));
+
+		let a_steps = steps.clone();
+		let _a_handler = my_callback.add(Box::new(move |x| {
+			let steps = a_steps.clone();
+			Box::pin(async move {
+				steps.borrow_mut().push(format!("a start {}", x));
+				YieldNow::default().await;
+				steps.borrow_mut().push(format!("a end {}", x));
+			})
+		}));
+		let b_steps = steps.clone();
+		let _b_handler = my_callback.add(Box::new(move |x| {
+			let steps = b_steps.clone();
+			Box::pin(async move {
+				steps.borrow_mut().push(format!("b start {}", x));
+				YieldNow::default().await;
+				steps.borrow_mut().push(format!("b end {}", x));
+			})
+		}));
+
+		let future = my_callback.run_all_async(1);
+		assert!(steps.borrow().is_empty());
+		block_on(future);
+		assert_eq!(vec!["a start 1", "a end 1", "b start 1", "b end 1"], *steps.borrow());
+	}
+
+	#[test]
+	fn concurrent_dispatch() {
+		let my_callback: AsyncCallbackRegistry<u32> = Default::default();
+		let steps = Rc::new(RefCell::new(Vec::new()));
+
+		let a_steps = steps.clone();
+		let _a_handler = my_callback.add(Box::new(move |x| {
+			let steps = a_steps.clone();
+			Box::pin(async move {
+				steps.borrow_mut().push(format!("a start {}", x));
+				YieldNow::default().await;
+				steps.borrow_mut().push(format!("a end {}", x));
+			})
+		}));
+		let b_steps = steps.clone();
+		let _b_handler = my_callback.add(Box::new(move |x| {
+			let steps = b_steps.clone();
+			Box::pin(async move {
+				steps.borrow_mut().push(format!("b start {}", x));
+				YieldNow::default().await;
+				steps.borrow_mut().push(format!("b end {}", x));
+			})
+		}));
+
+		block_on(my_callback.run_all_concurrent(2));
+		assert_eq!(vec!["a start 2", "b start 2", "a end 2", "b end 2"], *steps.borrow());
+	}
+
+	#[test]
+	fn dropped_subscription() {
+		let my_callback: AsyncCallbackRegistry<u32> = Default::default();
+		let steps = Rc::new(RefCell::new(Vec::new()));
+
+		let a_steps = steps.clone();
+		let a_handler = my_callback.add(Box::new(move |x| {
+			let steps = a_steps.clone();
+			Box::pin(async move {
+				steps.borrow_mut().push(format!("a start {}", x));
+				YieldNow::default().await;
+				steps.borrow_mut().push(format!("a end {}", x));
+			})
+		}));
+		let b_steps = steps.clone();
+		let _b_handler = my_callback.add(Box::new(move |x| {
+			let steps = b_steps.clone();
+			Box::pin(async move {
+				steps.borrow_mut().push(format!("b start {}", x));
+				YieldNow::default().await;
+				steps.borrow_mut().push(format!("b end {}", x));
+			})
+		}));
+
+		drop(a_handler);
+		block_on(my_callback.run_all_concurrent(3));
+		block_on(my_callback.run_all_async(4));
+		assert_eq!(vec!["b start 3", "b end 3", "b start 4", "b end 4"], *steps.borrow());
+	}
+}
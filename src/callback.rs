@@ -1,13 +1,30 @@
-use std::rc::Rc;
-
-/// Wrapper for the callback handler
-///
-/// This handler only uses a Rc for () but the CallbackHandler is defined so the implementation
-/// details can be decoupled.
-///
-/// The `Rc` automatically handles the drop situation so the CallbackManager only needs to check
-/// the number of strong references.
-type CallbackHandler = Rc<()>;
+use crate::combiner::Combiner;
+use crate::dispatch::{
+	CallbackPanic,
+	CallbackStats,
+	DispatchReport,
+	ErrorPolicy,
+	OverflowPolicy,
+	PanicPolicy,
+};
+use crate::slab::{Slab, SlabKey};
+use crate::subscription::{
+	Disconnected,
+	Subscription,
+	SubscriptionId,
+	SubscriptionInfo,
+	SubscriptionState,
+};
+use crate::tuple_callback::ArgsCallback;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::any::Any;
+use std::iter;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe, Location};
+use std::rc::{Rc, Weak};
+use std::time::Instant;
 
 /// Saves the callbacks and execute then when requested.
 #[derive(Default)]
@@ -16,49 +33,907 @@ pub struct CallbackManager {
 }
 
 impl CallbackManager {
-	/// The `add` method accepts a closure with no arguments and returns an handler.
-	/// Once the handler is dropped the closure will not be executed anymore.
-	/// The mutability on self is expected cause a closure is being added.
-	pub fn add(&mut self, callback: Box<dyn Fn()>) -> CallbackHandler {
+	/// The `add` method accepts a closure with no arguments and returns a `Subscription`.
+	/// Once the `Subscription` is dropped the closure will not be executed anymore.
+	#[track_caller]
+	pub fn add(&self, mut callback: Box<dyn FnMut()>) -> Subscription {
 		self.manager.add(Box::new(move |_| callback()))
 	}
 
-	/// The `run_all` method runs all the closures if their handler was not dropped it.
-	/// The mutability on self is expected cause any closure which had his handler dropped will
-	/// also be dropped.
-	pub fn run_all(&mut self) {
+	/// The `add_with_priority` method works like `add` but the closure runs before every closure
+	/// with a lower priority, see `CallbackRegistry::add_with_priority`.
+	#[track_caller]
+	pub fn add_with_priority(
+		&self, mut callback: Box<dyn FnMut()>, priority: i32,
+	) -> Subscription {
+		self.manager.add_with_priority(Box::new(move |_| callback()), priority)
+	}
+
+	/// The `add_once` method accepts a closure which is going to be executed only on the next
+	/// `run_all`, see `CallbackRegistry::add_once`.
+	#[track_caller]
+	pub fn add_once(&self, callback: Box<dyn FnOnce()>) -> Subscription {
+		self.manager.add_once(Box::new(move |_| callback()))
+	}
+
+	/// The `run_all` method runs all the closures if their `Subscription` was not dropped it.
+	/// It can be called from one of the closures, see `CallbackRegistry::run_all`.
+	pub fn run_all(&self) {
 		self.manager.run_all(())
 	}
+
+	/// The number of closures connected to the manager, see `CallbackRegistry::len`.
+	pub fn len(&self) -> usize {
+		self.manager.len()
+	}
+
+	/// Indicates if there are no closures connected to the manager.
+	pub fn is_empty(&self) -> bool {
+		self.manager.is_empty()
+	}
+
+	/// The `subscriptions` method describes the closures connected to the manager, see
+	/// `CallbackRegistry::subscriptions`.
+	pub fn subscriptions(&self) -> impl Iterator<Item=SubscriptionInfo> {
+		self.manager.subscriptions()
+	}
+
+	/// The `set_instrumented` method enables or disables the metrics of the closures, see
+	/// `CallbackRegistry::set_instrumented`.
+	pub fn set_instrumented(&self, instrumented: bool) {
+		self.manager.set_instrumented(instrumented)
+	}
+
+	/// The `metrics` method returns a snapshot of the metrics of the closures, see
+	/// `CallbackRegistry::metrics`.
+	pub fn metrics(&self) -> Vec<(SubscriptionInfo, CallbackStats)> {
+		self.manager.metrics()
+	}
+}
+
+/// A closure which can only be executed once.
+type OnceCallback<ParamType, ReturnType> = Box<dyn FnOnce(&ParamType) -> ReturnType>;
+
+/// A closure which can only be executed once, taking the parameter by value.
+type OnceByValueCallback<ParamType, ReturnType> = Box<dyn FnOnce(ParamType) -> ReturnType>;
+
+/// A predicate deciding if a closure runs for a parameter.
+type Filter<ParamType> = Box<dyn Fn(&ParamType) -> bool>;
+
+/// A closure bound to a receiver, taking the parameter by value.
+type BoundCallback<T, ParamType, ReturnType> = Box<dyn FnMut(&T, ParamType) -> ReturnType>;
+
+/// A closure bound to a receiver, taking the parameter by reference.
+type RefBoundCallback<T, ParamType, ReturnType> = Box<dyn FnMut(&T, &ParamType) -> ReturnType>;
+
+/// The callbacks are dispatched the parameter by reference, so the same value can be shared by
+/// all of them without requiring `Copy`.
+///
+/// The closures taking the parameter by value are stored with the function cloning it, instead of
+/// being wrapped in a closure taking it by reference, so they do not need to be `'static` when the
+/// parameter borrows something.
+enum Callback<ParamType, ReturnType> {
+	/// Executed on every `run_all`, the closure is mutable cause the callbacks are taken out of the
+	/// registry while they run.
+	Repeated(Box<dyn FnMut(&ParamType) -> ReturnType>),
+	/// Works like `Repeated`, the closure receives its own copy of the parameter.
+	RepeatedByValue(Box<dyn FnMut(ParamType) -> ReturnType>, fn(&ParamType) -> ParamType),
+	/// Executed only once, then the closure is taken out leaving `None`.
+	Once(Option<OnceCallback<ParamType, ReturnType>>),
+	/// Works like `Once`, the closure receives its own copy of the parameter.
+	OnceByValue(Option<OnceByValueCallback<ParamType, ReturnType>>, fn(&ParamType) -> ParamType),
+	/// Works like `RepeatedByValue`, the closure receives the elements of the tuple parameter as
+	/// separate arguments.
+	RepeatedArgs(Box<dyn ArgsCallback<ParamType, ReturnType>>),
+}
+
+impl<ParamType, ReturnType> Callback<ParamType, ReturnType> {
+	/// Runs the closure, returns `None` if it was a one-shot closure that already ran.
+	///
+	/// It is inlined so the dispatch calls the closure directly, this is measured by
+	/// `benches/dispatch.rs`.
+	#[inline]
+	fn call(&mut self, param: &ParamType) -> Option<ReturnType> {
+		match self {
+			Callback::Repeated(callback) => Some(callback(param)),
+			Callback::RepeatedByValue(callback, clone) => Some(callback(clone(param))),
+			Callback::Once(callback) => callback.take().map(|callback| callback(param)),
+			Callback::OnceByValue(callback, clone) =>
+				callback.take().map(|callback| callback(clone(param))),
+			Callback::RepeatedArgs(callback) => Some(callback.call(param)),
+		}
+	}
+
+	/// Indicates if the closure can still be executed.
+	fn is_pending(&self) -> bool {
+		match self {
+			Callback::Repeated(_) | Callback::RepeatedByValue(..) => true,
+			Callback::RepeatedArgs(_) => true,
+			Callback::Once(callback) => callback.is_some(),
+			Callback::OnceByValue(callback, _) => callback.is_some(),
+		}
+	}
+}
+
+/// The priority used by the methods which do not receive one.
+pub const DEFAULT_PRIORITY: i32 = 0;
+
+/// A callback registered in a `CallbackRegistry`.
+struct Entry<ParamType, ReturnType> {
+	callback: Callback<ParamType, ReturnType>,
+	subscription: Rc<SubscriptionState>,
+	/// The object the closure is bound to, the closure is removed once it is dropped.
+	receiver: Option<Weak<dyn Any>>,
+	/// Number of times the closure panicked, only counted when the panics are isolated.
+	panics: u32,
+	/// The closure only runs for the parameters accepted by the filter.
+	filter: Option<Filter<ParamType>>,
+	/// Where the closure was added.
+	location: &'static Location<'static>,
+	/// Created on the first call made with the instrumentation enabled, it is boxed so the
+	/// registries which are not instrumented do not pay for its size.
+	stats: Option<Box<CallbackStats>>,
+}
+
+impl<ParamType, ReturnType> Entry<ParamType, ReturnType> {
+	/// Indicates if the closure is still connected and its receiver, if any, is alive.
+	fn is_alive(&self) -> bool {
+		self.subscription.is_connected()
+			&& self.receiver.as_ref().is_none_or(|receiver| receiver.strong_count() > 0)
+	}
+
+	/// Runs the closure unless it is blocked or filtered out, a one-shot closure is disconnected
+	/// after running.
+	///
+	/// Unless the `policy` is `PanicPolicy::Propagate` a panic is caught and returned.
+	/// The call is added to the metrics when it is `instrumented`.
+	fn call(
+		&mut self, param: &ParamType, policy: PanicPolicy, instrumented: bool,
+	) -> Result<Option<ReturnType>, CallbackPanic> {
+		if self.subscription.is_blocked() {
+			return Ok(None);
+		}
+		if self.filter.as_ref().is_some_and(|filter| !filter(param)) {
+			return Ok(None);
+		}
+		if !instrumented && policy == PanicPolicy::Propagate {
+			// Most registries neither record metrics nor catch panics, they skip the bookkeeping.
+			let resp = self.callback.call(param);
+			if !self.callback.is_pending() {
+				self.subscription.disconnect();
+			}
+			return Ok(resp);
+		}
+		let start = if instrumented { Some(Instant::now()) } else { None };
+		let resp = match policy {
+			PanicPolicy::Propagate => Ok(self.callback.call(param)),
+			_ => panic::catch_unwind(AssertUnwindSafe(|| self.callback.call(param))),
+		};
+		if let Some(start) = start {
+			let stats = self.stats.get_or_insert_with(Default::default);
+			stats.add_call(start.elapsed());
+			if resp.is_err() {
+				stats.add_panic();
+			}
+		}
+		if !self.callback.is_pending() {
+			self.subscription.disconnect();
+		}
+		resp.map_err(|payload| {
+			self.panics += 1;
+			if let PanicPolicy::DisconnectAfter(limit) = policy {
+				if self.panics >= limit {
+					self.subscription.disconnect();
+				}
+			}
+			CallbackPanic::new(
+				self.subscription.id(), payload.as_ref(), !self.subscription.is_connected())
+		})
+	}
+}
+
+/// The subscription is disconnected when its callback is dropped, including when the registry
+/// itself is dropped.
+impl<ParamType, ReturnType> Drop for Entry<ParamType, ReturnType> {
+	fn drop(&mut self) {
+		self.subscription.disconnect();
+	}
+}
+
+/// A callback stored in a `Bucket` with the key its subscription knows it by.
+struct Node<ParamType, ReturnType> {
+	entry: Entry<ParamType, ReturnType>,
+	key: SlabKey,
+}
+
+/// The callbacks of a priority in the order they were added.
+///
+/// A removed callback leaves an empty slot behind, so removing one does not move the others.
+struct Bucket<ParamType, ReturnType> {
+	priority: i32,
+	slots: Vec<Option<Node<ParamType, ReturnType>>>,
+	/// The number of empty slots.
+	removed: usize,
+}
+
+impl<ParamType, ReturnType> Bucket<ParamType, ReturnType> {
+	/// Drops the empty slots, updating the `positions` of the callbacks which moved.
+	fn compact(&mut self, positions: &mut Slab<(i32, usize)>) {
+		self.slots.retain(Option::is_some);
+		self.removed = 0;
+		for (position, node) in self.slots.iter().flatten().enumerate() {
+			if let Some(location) = positions.get_mut(node.key) {
+				location.1 = position;
+			}
+		}
+	}
+}
+
+/// The callbacks of a registry ordered by priority.
+///
+/// Each priority keeps its callbacks next to each other in a `Bucket`, so the dispatch goes
+/// through them in order without following pointers, and adding one only pushes it at the end of
+/// its bucket. The `Slab` gives the position of a callback from the key known by its subscription,
+/// so removing one only empties its slot.
+/// A bucket drops its empty slots once they are half of it, so the dispatch mostly goes through
+/// connected callbacks while the cost of moving the others is spread over the removals.
+struct Callbacks<ParamType, ReturnType> {
+	/// The priority and the position in its bucket of each callback.
+	positions: Slab<(i32, usize)>,
+	/// The buckets from the highest priority to the lowest, there are usually only a few of them.
+	buckets: Vec<Bucket<ParamType, ReturnType>>,
+}
+
+/// No callbacks, it is what a dispatch leaves in the registry while it holds the callbacks.
+impl<ParamType, ReturnType> Default for Callbacks<ParamType, ReturnType> {
+	fn default() -> Self {
+		Self {
+			positions: Slab::default(),
+			buckets: Vec::new(),
+		}
+	}
+}
+
+impl<ParamType, ReturnType> Callbacks<ParamType, ReturnType> {
+	/// Adds the entry after all the entries with a higher or equal priority.
+	fn insert(&mut self, entry: Entry<ParamType, ReturnType>, priority: i32) -> SlabKey {
+		let index = match self.bucket(priority) {
+			Ok(index) => index,
+			Err(index) => {
+				self.buckets.insert(index, Bucket {
+					priority,
+					slots: Vec::new(),
+					removed: 0,
+				});
+				index
+			}
+		};
+		let bucket = &mut self.buckets[index];
+		let key = self.positions.insert((priority, bucket.slots.len()));
+		bucket.slots.push(Some(Node {
+			entry,
+			key,
+		}));
+		key
+	}
+
+	/// Removes the entry of the `key`, it returns `None` if it was already removed.
+	fn remove(&mut self, key: SlabKey) -> Option<Entry<ParamType, ReturnType>> {
+		let (priority, position) = self.positions.remove(key)?;
+		let index = self.bucket(priority).expect("the priority has entries");
+		let bucket = &mut self.buckets[index];
+		let node = bucket.slots[position].take().expect("the positions point to the entries");
+		bucket.removed += 1;
+		if bucket.removed == bucket.slots.len() {
+			self.buckets.remove(index);
+		} else if bucket.removed * 2 >= bucket.slots.len() {
+			bucket.compact(&mut self.positions);
+		}
+		Some(node.entry)
+	}
+
+	/// The index of the bucket of `priority`, or where to insert it, see `slice::binary_search`.
+	fn bucket(&self, priority: i32) -> Result<usize, usize> {
+		self.buckets.binary_search_by(|bucket| priority.cmp(&bucket.priority))
+	}
+
+	/// The number of entries, including the disconnected ones not removed yet.
+	#[cfg(test)]
+	fn len(&self) -> usize {
+		self.positions.len()
+	}
+
+	/// The entries in the order of the dispatch, with their priority.
+	fn iter(&self) -> impl Iterator<Item=(i32, &Entry<ParamType, ReturnType>)> + '_ {
+		self.buckets.iter().flat_map(|bucket| {
+			bucket.slots.iter().flatten().map(move |node| (bucket.priority, &node.entry))
+		})
+	}
 }
 
 /// CallbackRegistry tracks callbacks and then call all then when requested.
 /// This implementation accepts one parameter.
-/// The `ParamType` needs to implement `Copy` cause it is going to be sent to all the callbacks.
-#[derive(Default)]
-pub struct CallbackRegistry<ParamType: Copy> {
-	/// It is using a Box for the futures here cause the vector needs a sized type.
-	callbacks: Vec<(Box<dyn Fn(ParamType)>, CallbackHandler)>,
-}
-
-impl<ParamType: Copy> CallbackRegistry<ParamType> {
-	/// The `add` method accepts a closure with with a parameter and returns an handler.
-	/// Once the handler is dropped the closure will not be executed anymore.
-	/// The mutability on self is expected cause a closure is being added.
-	pub fn add(&mut self, callback: Box<dyn Fn(ParamType)>) -> CallbackHandler {
-		let resp = Rc::new(());
-		self.callbacks.push((callback, resp.clone()));
+/// The parameter is dispatched by reference, closures taking it by value are also accepted as long
+/// as `ParamType` implements `Clone`, then each one of them receives its own copy.
+///
+/// The closures can also return a value of type `ReturnType`, the results are folded by a
+/// `Combiner` when the registry is run with `run_all_with`.
+///
+/// The closures run ordered by priority, the higher first, closures with the same priority run in
+/// the order they were added.
+///
+/// All the methods take `&self`, so the registry can be shared, usually with a `Rc`, and the
+/// closures are free to use it while they are running:
+/// - A closure added during a dispatch is not executed by it, it starts running on the next one.
+/// - A closure which has its `Subscription` dropped during a dispatch is not executed anymore,
+///   even if it did not run yet in the current one.
+/// - A `run_all` called during a dispatch is queued, it runs right after the current dispatch
+///   finishes, the queued calls run in the order they were made.
+pub struct CallbackRegistry<ParamType, ReturnType = ()> {
+	/// It is using a Box for the closures here cause the slab needs a sized type.
+	callbacks: RefCell<Callbacks<ParamType, ReturnType>>,
+	/// The keys of the callbacks disconnected, they are removed when there is no dispatch running.
+	/// It is shared with the subscriptions, so removing a callback does not require looking for it.
+	disconnected: Rc<Disconnected>,
+	/// The callbacks added during a dispatch, with their priorities, they are inserted once it
+	/// finishes.
+	added: RefCell<Vec<(Entry<ParamType, ReturnType>, i32)>>,
+	/// The number of callbacks stored, including the ones held by a running dispatch, so they can
+	/// be counted while they are taken out.
+	stored: Cell<usize>,
+	/// Indicates if the callbacks are running.
+	dispatching: Cell<bool>,
+	/// Parameters of the `run_all` calls made during a dispatch.
+	queued: RefCell<VecDeque<ParamType>>,
+	panic_policy: Cell<PanicPolicy>,
+	/// Indicates if the metrics of the closures are recorded.
+	instrumented: Cell<bool>,
+	/// The panics caught since the last `take_panics`.
+	panics: RefCell<Vec<CallbackPanic>>,
+	/// Parameters sent with `post` waiting for a `flush`.
+	posted: RefCell<VecDeque<ParamType>>,
+	/// The maximum number of posted parameters and what to do when it is reached.
+	posted_limit: Cell<Option<(usize, OverflowPolicy)>>,
+}
+
+/// Implemented by hand cause deriving it would require `ParamType` to implement `Default`.
+impl<ParamType, ReturnType> Default for CallbackRegistry<ParamType, ReturnType> {
+	fn default() -> Self {
+		Self {
+			callbacks: RefCell::new(Callbacks::default()),
+			disconnected: Rc::new(RefCell::new(Vec::new())),
+			added: RefCell::new(Vec::new()),
+			stored: Cell::new(0),
+			dispatching: Cell::new(false),
+			queued: RefCell::new(VecDeque::new()),
+			panic_policy: Cell::new(PanicPolicy::Propagate),
+			instrumented: Cell::new(false),
+			panics: RefCell::new(Vec::new()),
+			posted: RefCell::new(VecDeque::new()),
+			posted_limit: Cell::new(None),
+		}
+	}
+}
+
+impl<ParamType: Clone, ReturnType> CallbackRegistry<ParamType, ReturnType> {
+	/// The `add` method accepts a closure with with a parameter and returns a `Subscription`.
+	/// Once the `Subscription` is dropped the closure will not be executed anymore.
+	///
+	/// The closure is a `FnMut`, so it can change its captured state directly, there is no need to
+	/// wrap it in a `Cell` or `RefCell`.
+	#[track_caller]
+	pub fn add(&self, callback: Box<dyn FnMut(ParamType) -> ReturnType>) -> Subscription {
+		self.add_with_priority(callback, DEFAULT_PRIORITY)
+	}
+
+	/// The `add_with_priority` method works like `add` but the closure runs before every closure
+	/// with a lower priority and after every closure with a higher one.
+	#[track_caller]
+	pub fn add_with_priority(
+		&self, callback: Box<dyn FnMut(ParamType) -> ReturnType>, priority: i32,
+	) -> Subscription {
+		self.insert(Callback::RepeatedByValue(callback, ParamType::clone), priority)
+	}
+
+	/// The `add_once` method accepts a closure which is going to be executed only on the next
+	/// `run_all`, after that it is removed automatically.
+	/// It is possible to cancel the execution by dropping the `Subscription` before that.
+	///
+	/// The closure is a `FnOnce`, so it can move its captured state out.
+	#[track_caller]
+	pub fn add_once(
+		&self, callback: OnceByValueCallback<ParamType, ReturnType>,
+	) -> Subscription {
+		self.insert(Callback::OnceByValue(Some(callback), ParamType::clone), DEFAULT_PRIORITY)
+	}
+
+	/// The `add_filtered` method accepts a closure which only runs for the parameters accepted by
+	/// the `predicate`, the `predicate` is evaluated before the parameter is cloned.
+	#[track_caller]
+	pub fn add_filtered(
+		&self, predicate: Filter<ParamType>, callback: Box<dyn FnMut(ParamType) -> ReturnType>,
+	) -> Subscription {
+		let callback = Callback::RepeatedByValue(callback, ParamType::clone);
+		self.insert_entry(callback, DEFAULT_PRIORITY, None, Some(predicate))
+	}
+
+	/// The `add_bound` method accepts a closure bound to the `receiver`, it receives the
+	/// `receiver` together with the parameter.
+	///
+	/// The registry only keeps a `Weak` reference to the `receiver`, so the closure does not keep
+	/// it alive. There is no `Subscription`, the closure is removed once the `receiver` is dropped.
+	#[track_caller]
+	pub fn add_bound<T: 'static>(
+		&self, receiver: &Rc<T>, mut callback: BoundCallback<T, ParamType, ReturnType>,
+	) where ParamType: 'static, ReturnType: 'static {
+		self.add_ref_bound(receiver, Box::new(
+			move |receiver: &T, param: &ParamType| callback(receiver, param.clone())))
+	}
+}
+
+impl<ParamType, ReturnType> CallbackRegistry<ParamType, ReturnType> {
+	/// The `add_ref` method accepts a closure receiving the parameter by reference and returns an
+	/// `Subscription`, it does not need to clone the parameter.
+	/// Once the `Subscription` is dropped the closure will not be executed anymore.
+	#[track_caller]
+	pub fn add_ref(
+		&self, callback: Box<dyn FnMut(&ParamType) -> ReturnType>,
+	) -> Subscription {
+		self.add_ref_with_priority(callback, DEFAULT_PRIORITY)
+	}
+
+	/// The `add_ref_with_priority` method is the `add_ref` counterpart of `add_with_priority`.
+	#[track_caller]
+	pub fn add_ref_with_priority(
+		&self, callback: Box<dyn FnMut(&ParamType) -> ReturnType>, priority: i32,
+	) -> Subscription {
+		self.insert(Callback::Repeated(callback), priority)
+	}
+
+	/// The `add_ref_once` method is the `add_ref` counterpart of `add_once`.
+	#[track_caller]
+	pub fn add_ref_once(
+		&self, callback: OnceCallback<ParamType, ReturnType>,
+	) -> Subscription {
+		self.insert(Callback::Once(Some(callback)), DEFAULT_PRIORITY)
+	}
+
+	/// The `add_ref_filtered` method is the `add_ref` counterpart of `add_filtered`.
+	#[track_caller]
+	pub fn add_ref_filtered(
+		&self, predicate: Filter<ParamType>, callback: Box<dyn FnMut(&ParamType) -> ReturnType>,
+	) -> Subscription {
+		self.insert_entry(Callback::Repeated(callback), DEFAULT_PRIORITY, None, Some(predicate))
+	}
+
+	/// The `add_ref_bound` method is the `add_ref` counterpart of `add_bound`.
+	#[track_caller]
+	pub fn add_ref_bound<T: 'static>(
+		&self, receiver: &Rc<T>, callback: RefBoundCallback<T, ParamType, ReturnType>,
+	) where ParamType: 'static, ReturnType: 'static {
+		self.insert_bound(receiver, callback).detach();
+	}
+
+	/// The `run_all` method runs all the closures if their `Subscription` was not dropped it.
+	/// The values returned by the closures are discarded.
+	///
+	/// When it is called from one of the closures the call is queued and runs once the current
+	/// dispatch finishes, so the closures are never executed recursively.
+	pub fn run_all(&self, param: ParamType) {
+		if self.dispatching.get() {
+			self.queued.borrow_mut().push_back(param);
+			return;
+		}
+		Dispatch::start(self).run(&param).for_each(drop);
+		self.run_queued();
+	}
+
+	/// The `run_all_with` method runs the closures and folds their results using `combiner`.
+	///
+	/// The closures are called lazily while the combiner consumes the results, so a combiner which
+	/// stops early, like `combiner::FirstSome`, skips the remaining closures.
+	///
+	/// The results can not be queued, so it panics if called from one of the closures.
+	pub fn run_all_with<C: Combiner<ReturnType>>(
+		&self, param: ParamType, combiner: C,
+	) -> C::Output {
+		self.assert_not_dispatching("run_all_with");
+		let resp = combiner.combine(Dispatch::start(self).run(&param));
+		self.run_queued();
 		resp
 	}
 
-	/// The `run_all` method runs all the closures if their handler was not dropped it.
-	/// The mutability on self is expected cause any closure which had his handler dropped will
-	/// also be dropped.
-	pub fn run_all(&mut self, param: ParamType) {
-		self.callbacks.retain(
-			|(_, handler)| Rc::strong_count(handler) > 1);
-		for (callback, _) in &self.callbacks {
-			callback(param);
+	/// The `post` method enqueues the parameter, the closures only run for it on the next `flush`.
+	///
+	/// When the queue is bounded and full an event is discarded following the `OverflowPolicy`,
+	/// the discarded event is returned.
+	/// A queue bounded to 0 has no older event to drop, so the new one is discarded whatever the
+	/// policy.
+	pub fn post(&self, param: ParamType) -> Option<ParamType> {
+		let mut posted = self.posted.borrow_mut();
+		let discarded = match self.posted_limit.get() {
+			Some((capacity, _)) if posted.len() < capacity => None,
+			Some((0, _)) | Some((_, OverflowPolicy::DropNewest)) => return Some(param),
+			Some((_, OverflowPolicy::DropOldest)) => posted.pop_front(),
+			None => None,
+		};
+		posted.push_back(param);
+		discarded
+	}
+
+	/// The `flush` method runs the closures for every parameter posted, in the order they were
+	/// posted.
+	///
+	/// The parameters posted by the closures during the flush wait for the next one, so a closure
+	/// posting on every call does not make it run forever.
+	pub fn flush(&self) {
+		let posted = self.posted.take();
+		for param in posted {
+			self.run_all(param);
+		}
+	}
+
+	/// The number of parameters waiting for a `flush`.
+	pub fn posted_len(&self) -> usize {
+		self.posted.borrow().len()
+	}
+
+	/// The `set_posted_limit` method bounds the queue used by `post`, `None` makes it unbounded,
+	/// which is the default.
+	///
+	/// When a smaller limit is set the oldest parameters already posted are kept, unless the
+	/// `policy` is `OverflowPolicy::DropOldest`.
+	pub fn set_posted_limit(&self, limit: Option<(usize, OverflowPolicy)>) {
+		self.posted_limit.set(limit);
+		if let Some((capacity, policy)) = limit {
+			let mut posted = self.posted.borrow_mut();
+			while posted.len() > capacity {
+				match policy {
+					OverflowPolicy::DropNewest => posted.pop_back(),
+					OverflowPolicy::DropOldest => posted.pop_front(),
+				};
+			}
+		}
+	}
+
+	/// The `set_panic_policy` method changes how the panics of the closures are handled, by default
+	/// they are propagated.
+	///
+	/// When the panics are isolated a panicking closure does not stop the dispatch, the panic is
+	/// saved in the registry and can be read with `take_panics`.
+	pub fn set_panic_policy(&self, policy: PanicPolicy) {
+		self.panic_policy.set(policy);
+	}
+
+	/// The `take_panics` method returns the panics caught since the last call, in the order they
+	/// happened.
+	pub fn take_panics(&self) -> Vec<CallbackPanic> {
+		self.panics.take()
+	}
+
+	/// Checks that the results of the closures can be returned, see `run_all_with`.
+	fn assert_not_dispatching(&self, method: &str) {
+		assert!(
+			!self.dispatching.get(),
+			"{} can not be called while the callbacks of the registry are running", method);
+	}
+
+	/// The number of closures connected to the registry.
+	///
+	/// The closures bound to a receiver are counted until a dispatch finds out the receiver was
+	/// dropped.
+	pub fn len(&self) -> usize {
+		let added = self.added.borrow().iter()
+			.filter(|(entry, _)| entry.subscription.is_connected())
+			.count();
+		self.stored.get() - self.disconnected.borrow().len() + added
+	}
+
+	/// Indicates if there are no closures connected to the registry, see `len`.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The `subscriptions` method describes the closures connected to the registry, in the order
+	/// they run, it is meant to find the closures which should have been disconnected.
+	///
+	/// The closures are not available while they are running, so it panics if called from one of
+	/// the closures.
+	pub fn subscriptions(&self) -> impl Iterator<Item=SubscriptionInfo> {
+		self.describe("subscriptions", |info, _| info).into_iter()
+	}
+
+	/// The `set_instrumented` method enables or disables the metrics of the closures, they are
+	/// disabled by default.
+	///
+	/// The metrics already recorded are kept when the instrumentation is disabled.
+	pub fn set_instrumented(&self, instrumented: bool) {
+		self.instrumented.set(instrumented);
+	}
+
+	/// The `metrics` method returns a snapshot of the metrics of the closures connected to the
+	/// registry, in the order they run, see `set_instrumented`.
+	///
+	/// Like `subscriptions`, it panics if called from one of the closures.
+	pub fn metrics(&self) -> Vec<(SubscriptionInfo, CallbackStats)> {
+		self.describe("metrics", |info, stats| (info, stats))
+	}
+
+	/// Describes the closures connected to the registry with `description`.
+	fn describe<T>(
+		&self, method: &str, description: impl Fn(SubscriptionInfo, CallbackStats) -> T,
+	) -> Vec<T> {
+		self.assert_not_dispatching(method);
+		self.remove_disconnected();
+		self.callbacks.borrow().iter()
+			.filter(|(_, entry)| entry.is_alive())
+			.map(|(priority, entry)| description(
+				SubscriptionInfo::new(&entry.subscription, priority, entry.location),
+				entry.stats.as_deref().copied().unwrap_or_default()))
+			.collect()
+	}
+
+	/// Removes the closures disconnected, unless there is a dispatch running.
+	fn remove_disconnected(&self) {
+		if self.dispatching.get() {
+			return;
+		}
+		let keys = self.disconnected.take();
+		if keys.is_empty() {
+			return;
+		}
+		let removed: Vec<_> = {
+			let mut callbacks = self.callbacks.borrow_mut();
+			keys.into_iter().filter_map(|key| callbacks.remove(key)).collect()
+		};
+		self.stored.set(self.stored.get() - removed.len());
+		// The closures are dropped after releasing the callbacks, their captured state is free to
+		// use the registry.
+		drop(removed);
+	}
+
+	/// Runs the `run_all` calls queued by the closures.
+	fn run_queued(&self) {
+		loop {
+			let param = self.queued.borrow_mut().pop_front();
+			match param {
+				None => break,
+				Some(param) => Dispatch::start(self).run(&param).for_each(drop),
+			}
+		}
+	}
+
+	/// Adds the closure bound to the `receiver`, see `add_ref_bound`.
+	#[track_caller]
+	fn insert_bound<T: 'static>(
+		&self, receiver: &Rc<T>, mut callback: RefBoundCallback<T, ParamType, ReturnType>,
+	) -> Subscription where ParamType: 'static, ReturnType: 'static {
+		let weak_receiver = Rc::downgrade(receiver);
+		let callback: Box<dyn FnMut(&ParamType) -> ReturnType> = Box::new(move |param| {
+			let receiver = weak_receiver.upgrade().expect("receiver checked before the call");
+			callback(&receiver, param)
+		});
+		let receiver: Weak<dyn Any> = Rc::downgrade(receiver) as Weak<T>;
+		self.insert_entry(Callback::Repeated(callback), DEFAULT_PRIORITY, Some(receiver), None)
+	}
+
+	/// Adds a closure taking the elements of the tuple parameter as separate arguments, see
+	/// `add_args`.
+	#[track_caller]
+	pub(crate) fn insert_args(
+		&self, callback: Box<dyn ArgsCallback<ParamType, ReturnType>>,
+	) -> Subscription {
+		self.insert(Callback::RepeatedArgs(callback), DEFAULT_PRIORITY)
+	}
+
+	/// Adds the entry keeping the callbacks sorted by priority.
+	#[track_caller]
+	fn insert(
+		&self, callback: Callback<ParamType, ReturnType>, priority: i32,
+	) -> Subscription {
+		self.insert_entry(callback, priority, None, None)
+	}
+
+	/// Adds the entry with its optional receiver and filter keeping the callbacks sorted by
+	/// priority.
+	#[track_caller]
+	fn insert_entry(
+		&self, callback: Callback<ParamType, ReturnType>, priority: i32,
+		receiver: Option<Weak<dyn Any>>, filter: Option<Filter<ParamType>>,
+	) -> Subscription {
+		let subscription = SubscriptionState::new();
+		let entry = Entry {
+			callback,
+			subscription: subscription.clone(),
+			receiver,
+			panics: 0,
+			filter,
+			location: Location::caller(),
+			stats: None,
+		};
+		if self.dispatching.get() {
+			self.added.borrow_mut().push((entry, priority));
+		} else {
+			self.remove_disconnected();
+			self.link(entry, priority);
+		}
+		Subscription::new(subscription)
+	}
+
+	/// Inserts the entry in the callbacks, its subscription sends the key back once it is
+	/// disconnected.
+	fn link(&self, entry: Entry<ParamType, ReturnType>, priority: i32) {
+		let subscription = entry.subscription.clone();
+		let key = self.callbacks.borrow_mut().insert(entry, priority);
+		self.stored.set(self.stored.get() + 1);
+		subscription.set_registry(&self.disconnected, key);
+	}
+}
+
+impl<ParamType: 'static> CallbackRegistry<ParamType> {
+	/// The `forward_with` method forwards every parameter to the `other` registry, converted by
+	/// `map`, and returns a `Subscription`.
+	///
+	/// The registry only keeps a `Weak` reference to `other`, the forwarding is removed once
+	/// `other` or the `Subscription` is dropped.
+	///
+	/// A parameter is never forwarded to a registry which is running its closures, that only
+	/// happens when the forwarding goes back to a registry the parameter already went through, so
+	/// loops like two registries connected to each other stop there.
+	#[track_caller]
+	pub fn forward_with<U: 'static, OtherReturn: 'static>(
+		&self, other: &Rc<CallbackRegistry<U, OtherReturn>>,
+		mut map: impl FnMut(&ParamType) -> U + 'static,
+	) -> Subscription {
+		self.insert_bound(other, Box::new(move |other: &CallbackRegistry<U, OtherReturn>, param| {
+			if !other.dispatching.get() {
+				other.run_all(map(param));
+			}
+		}))
+	}
+
+	/// The `connect_to` method forwards every parameter to the `other` registry as it is, see
+	/// `forward_with`.
+	#[track_caller]
+	pub fn connect_to<OtherReturn: 'static>(
+		&self, other: &Rc<CallbackRegistry<ParamType, OtherReturn>>,
+	) -> Subscription where ParamType: Clone {
+		self.forward_with(other, ParamType::clone)
+	}
+}
+
+impl<ParamType, ErrorType: Debug> CallbackRegistry<ParamType, Result<(), ErrorType>> {
+	/// The `try_run_all` method runs the fallible closures following the `policy` for the errors,
+	/// it returns a report with the subscriptions which failed.
+	///
+	/// Like `run_all_with`, it panics if called from one of the closures.
+	pub fn try_run_all(&self, param: ParamType, policy: ErrorPolicy) -> DispatchReport<ErrorType> {
+		self.assert_not_dispatching("try_run_all");
+		let mut report = DispatchReport::default();
+		let mut dispatch = Dispatch::start(self);
+		dispatch.is_error = Some(Result::is_err);
+		for (subscription, resp) in dispatch.run_with_ids(&param) {
+			match resp {
+				Ok(()) => report.add_success(),
+				Err(error) => {
+					if policy == ErrorPolicy::LogAndContinue {
+						eprintln!("Callback {:?} failed: {:?}", subscription, error);
+					}
+					report.add_failure(subscription, error);
+					if policy == ErrorPolicy::StopAtFirstError {
+						report.stop();
+						break;
+					}
+				}
+			}
+		}
+		// The callbacks must be back in the registry before the queued calls start their own
+		// dispatch.
+		drop(dispatch);
+		self.run_queued();
+		report
+	}
+}
+
+impl<ParamType: PartialEq, ReturnType> CallbackRegistry<ParamType, ReturnType> {
+	/// The `post_coalesced` method works like `post` but the parameter is discarded, and returned,
+	/// if an equal one is already waiting for the next `flush`.
+	pub fn post_coalesced(&self, param: ParamType) -> Option<ParamType> {
+		if self.posted.borrow().contains(&param) {
+			return Some(param);
+		}
+		self.post(param)
+	}
+}
+
+/// A running dispatch, it holds the callbacks taken out of the registry.
+///
+/// The callbacks are put back when it is dropped, even if one of the closures panics, together
+/// with the ones added meanwhile. The ones disconnected meanwhile are removed at that moment.
+struct Dispatch<'a, ParamType, ReturnType> {
+	registry: &'a CallbackRegistry<ParamType, ReturnType>,
+	callbacks: Callbacks<ParamType, ReturnType>,
+	/// Tells which results are errors, so they are added to the metrics.
+	is_error: Option<fn(&ReturnType) -> bool>,
+}
+
+impl<'a, ParamType, ReturnType> Dispatch<'a, ParamType, ReturnType> {
+	/// Takes the callbacks out of the `registry`, removing the closures which were disconnected.
+	fn start(registry: &'a CallbackRegistry<ParamType, ReturnType>) -> Self {
+		registry.remove_disconnected();
+		registry.dispatching.set(true);
+		Self {
+			registry,
+			callbacks: registry.callbacks.take(),
+			is_error: None,
+		}
+	}
+
+	/// Lazily runs the closures, the subscription and the receiver are checked right before each
+	/// call.
+	fn run<'b>(
+		&'b mut self, param: &'b ParamType,
+	) -> impl Iterator<Item=ReturnType> + 'b {
+		self.run_with_ids(param).map(|(_, resp)| resp)
+	}
+
+	/// Works like `run` but the results come with the subscription of their closure.
+	///
+	/// A closure which panicked while the panics are isolated does not produce a result, the panic
+	/// is saved in the registry.
+	/// The closures which already ran once and the ones which had their receiver dropped are
+	/// disconnected, so they are removed once the dispatch finishes.
+	fn run_with_ids<'b>(
+		&'b mut self, param: &'b ParamType,
+	) -> impl Iterator<Item=(SubscriptionId, ReturnType)> + 'b {
+		let registry = self.registry;
+		let policy = registry.panic_policy.get();
+		let instrumented = registry.instrumented.get();
+		let is_error = self.is_error;
+		let mut buckets = self.callbacks.buckets.iter_mut();
+		let mut slots = [].iter_mut();
+		iter::from_fn(move || {
+			loop {
+				let entry = match slots.next() {
+					Some(Some(Node { entry, .. })) => entry,
+					Some(None) => continue,
+					None => {
+						slots = buckets.next()?.slots.iter_mut();
+						continue;
+					}
+				};
+				if !entry.is_alive() {
+					entry.subscription.disconnect();
+					continue;
+				}
+				match entry.call(param, policy, instrumented) {
+					Ok(Some(resp)) => {
+						if let (Some(stats), Some(is_error)) = (entry.stats.as_mut(), is_error) {
+							if is_error(&resp) {
+								stats.add_error();
+							}
+						}
+						return Some((entry.subscription.id(), resp));
+					}
+					Ok(None) => {}
+					Err(panic) => registry.panics.borrow_mut().push(panic),
+				}
+			}
+		})
+	}
+}
+
+impl<'a, ParamType, ReturnType> Drop for Dispatch<'a, ParamType, ReturnType> {
+	fn drop(&mut self) {
+		let registry = self.registry;
+		registry.callbacks.replace(mem::take(&mut self.callbacks));
+		registry.dispatching.set(false);
+		for (entry, priority) in registry.added.take() {
+			if entry.subscription.is_connected() {
+				registry.link(entry, priority);
+			}
 		}
+		registry.remove_disconnected();
 	}
 }
 
@@ -68,10 +943,16 @@ mod tests {
 		CallbackManager,
 		CallbackRegistry,
 	};
+	use crate::combiner::{All, Collect};
+	use crate::dispatch::{OverflowPolicy, PanicPolicy};
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use std::thread;
+	use std::time::Duration;
 
 	#[test]
 	fn no_param_callback() {
-		let mut my_callback: CallbackManager = Default::default();
+		let my_callback: CallbackManager = Default::default();
 		println!("Out of callback no");
 
 		let _first_handler = my_callback.add(Box::new(||
@@ -96,7 +977,7 @@ mod tests {
 
 	#[test]
 	fn empty_param_callback() {
-		let mut my_callback: CallbackRegistry<()> = Default::default();
+		let my_callback: CallbackRegistry<()> = Default::default();
 		println!("Out of callback");
 
 		let _first_handler = my_callback.add(Box::new(|_| println!("First callback")));
@@ -117,7 +998,7 @@ mod tests {
 
 	#[test]
 	fn u32_param_callback() {
-		let mut my_callback: CallbackRegistry<u32> = Default::default();
+		let my_callback: CallbackRegistry<u32> = Default::default();
 		println!("Out of callback u32");
 
 		let _first_handler = my_callback.add(Box::new(|x|
@@ -139,4 +1020,631 @@ mod tests {
 
 		my_callback.run_all(7);
 	}
+
+	#[test]
+	fn borrowed_param_callback() {
+		let names = vec!["first".to_string(), "second".to_string()];
+		let my_callback: CallbackRegistry<&str> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(Box::new(move |name|
+			first_received.borrow_mut().push(name.to_uppercase())
+		));
+		let once_received = received.clone();
+		let _once_handler = my_callback.add_once(Box::new(move |name|
+			once_received.borrow_mut().push(format!("once {}", name))
+		));
+
+		for name in &names {
+			my_callback.run_all(name);
+		}
+		assert_eq!(vec!["FIRST", "once first", "SECOND"], *received.borrow());
+	}
+
+	/// Example of an event which is neither `Copy` nor `Default`.
+	#[derive(Clone, Debug, PartialEq)]
+	struct Moved {
+		from: (u32, u32),
+		to: (u32, u32),
+		reason: String,
+	}
+
+	#[test]
+	fn non_copy_param_callback() {
+		let my_callback: CallbackRegistry<Moved> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let by_value = received.clone();
+		let _first_handler = my_callback.add(Box::new(move |event: Moved|
+			by_value.borrow_mut().push(event.reason)
+		));
+		let by_ref = received.clone();
+		let _second_handler = my_callback.add_ref(Box::new(move |event: &Moved|
+			by_ref.borrow_mut().push(format!("{:?} -> {:?}", event.from, event.to))
+		));
+		my_callback.add_ref(Box::new(|event| {
+			println!("Dropped callback: {:?}", event);
+			panic!();
+		}));
+
+		my_callback.run_all(Moved { from: (1, 2), to: (3, 4), reason: "dragged".to_string() });
+		assert_eq!(vec!["dragged".to_string(), "(1, 2) -> (3, 4)".to_string()], *received.borrow());
+	}
+
+	#[test]
+	fn string_param_callback() {
+		let my_callback: CallbackRegistry<String> = Default::default();
+		let total_len = Rc::new(RefCell::new(0));
+
+		let len = total_len.clone();
+		let _handler = my_callback.add(Box::new(move |x: String| *len.borrow_mut() += x.len()));
+
+		my_callback.run_all("first".to_string());
+		my_callback.run_all("second".to_string());
+		assert_eq!(11, *total_len.borrow());
+	}
+
+	#[test]
+	fn veto_callback() {
+		let can_remove: CallbackRegistry<u32, bool> = Default::default();
+		let calls = Rc::new(RefCell::new(0));
+
+		assert!(can_remove.run_all_with(1, All));
+
+		let first_calls = calls.clone();
+		let _first_handler = can_remove.add(Box::new(move |x| {
+			*first_calls.borrow_mut() += 1;
+			x != 7
+		}));
+		let second_calls = calls.clone();
+		let _second_handler = can_remove.add(Box::new(move |_| {
+			*second_calls.borrow_mut() += 1;
+			true
+		}));
+
+		assert!(can_remove.run_all_with(1, All));
+		assert_eq!(2, *calls.borrow());
+		assert!(!can_remove.run_all_with(7, All));
+		assert_eq!(3, *calls.borrow());
+		assert_eq!(vec![false, true], can_remove.run_all_with(7, Collect));
+	}
+
+	#[test]
+	fn priority_callback() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let order = Rc::new(RefCell::new(Vec::new()));
+
+		let app_order = order.clone();
+		let _app_handler = my_callback.add(Box::new(move |_| app_order.borrow_mut().push("app")));
+		let after_order = order.clone();
+		let _after_handler = my_callback.add_with_priority(
+			Box::new(move |_| after_order.borrow_mut().push("after")), -10);
+		let logging_order = order.clone();
+		let _logging_handler = my_callback.add_with_priority(
+			Box::new(move |_| logging_order.borrow_mut().push("logging")), 10);
+		let validation_order = order.clone();
+		let _validation_handler = my_callback.add_ref_with_priority(
+			Box::new(move |_| validation_order.borrow_mut().push("validation")), 10);
+		let second_app_order = order.clone();
+		let _second_app_handler = my_callback.add(
+			Box::new(move |_| second_app_order.borrow_mut().push("second app")));
+
+		my_callback.run_all(1);
+		assert_eq!(
+			vec!["logging", "validation", "app", "second app", "after"], *order.borrow());
+	}
+
+	#[test]
+	fn priority_no_param_callback() {
+		let my_callback: CallbackManager = Default::default();
+		let order = Rc::new(RefCell::new(Vec::new()));
+
+		let first_order = order.clone();
+		let _first_handler = my_callback.add(Box::new(move || first_order.borrow_mut().push(1)));
+		let second_order = order.clone();
+		let _second_handler = my_callback.add_with_priority(
+			Box::new(move || second_order.borrow_mut().push(2)), 1);
+
+		my_callback.run_all();
+		assert_eq!(vec![2, 1], *order.borrow());
+	}
+
+	#[test]
+	fn once_callback() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let once_received = received.clone();
+		let _once_handler = my_callback.add_once(
+			Box::new(move |x| once_received.borrow_mut().push(x)));
+		let cancelled_handler = my_callback.add_once(Box::new(|_| panic!()));
+		drop(cancelled_handler);
+
+		my_callback.run_all(1);
+		my_callback.run_all(2);
+		assert_eq!(vec![1], *received.borrow());
+		assert_eq!(0, my_callback.callbacks.borrow().len());
+	}
+
+	#[test]
+	fn once_callback_moves_state_out() {
+		let my_callback: CallbackManager = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let message = "moved".to_string();
+		let once_received = received.clone();
+		let _once_handler = my_callback.add_once(Box::new(move || {
+			once_received.borrow_mut().push(message);
+		}));
+
+		my_callback.run_all();
+		my_callback.run_all();
+		assert_eq!(vec!["moved".to_string()], *received.borrow());
+	}
+
+	#[test]
+	fn mut_callback() {
+		let my_callback: CallbackRegistry<u32, u32> = Default::default();
+
+		let mut total = 0;
+		let _sum_handler = my_callback.add(Box::new(move |x| {
+			total += x;
+			total
+		}));
+		let mut calls = 0;
+		let _calls_handler = my_callback.add_ref(Box::new(move |_| {
+			calls += 1;
+			calls
+		}));
+
+		assert_eq!(vec![2, 1], my_callback.run_all_with(2, Collect));
+		assert_eq!(vec![5, 2], my_callback.run_all_with(3, Collect));
+	}
+
+	#[test]
+	fn mut_no_param_callback() {
+		let my_callback: CallbackManager = Default::default();
+		let mut history = Vec::new();
+		let seen = Rc::new(RefCell::new(0));
+
+		let last_seen = seen.clone();
+		let _handler = my_callback.add(Box::new(move || {
+			history.push(history.len());
+			*last_seen.borrow_mut() = history.len();
+		}));
+
+		my_callback.run_all();
+		my_callback.run_all();
+		my_callback.run_all();
+		assert_eq!(3, *seen.borrow());
+	}
+
+	#[test]
+	fn add_and_remove_during_run_all() {
+		let my_callback: Rc<CallbackRegistry<u32>> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+		let added_handlers = Rc::new(RefCell::new(Vec::new()));
+		let removed_handler = Rc::new(RefCell::new(None));
+
+		let registry = Rc::downgrade(&my_callback);
+		let adding_received = received.clone();
+		let adding_handlers = added_handlers.clone();
+		let removing_handler = removed_handler.clone();
+		let _adding_handler = my_callback.add(Box::new(move |x| {
+			adding_received.borrow_mut().push(format!("adding {}", x));
+			removing_handler.borrow_mut().take();
+			let added_received = adding_received.clone();
+			let added = registry.upgrade().unwrap().add(Box::new(move |x|
+				added_received.borrow_mut().push(format!("added {}", x))
+			));
+			adding_handlers.borrow_mut().push(added);
+		}));
+		removed_handler.replace(Some(my_callback.add(Box::new(|_| panic!()))));
+
+		my_callback.run_all(1);
+		assert_eq!(vec!["adding 1".to_string()], *received.borrow());
+		assert_eq!(1, added_handlers.borrow().len());
+
+		my_callback.run_all(2);
+		assert_eq!(
+			vec!["adding 1".to_string(), "adding 2".to_string(), "added 2".to_string()],
+			*received.borrow());
+		assert_eq!(3, my_callback.callbacks.borrow().len());
+	}
+
+	#[test]
+	fn nested_run_all_is_queued() {
+		let my_callback: Rc<CallbackRegistry<u32>> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let registry = Rc::downgrade(&my_callback);
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(Box::new(move |x| {
+			first_received.borrow_mut().push((1, x));
+			if x < 3 {
+				let registry = registry.upgrade().unwrap();
+				registry.run_all(x + 1);
+				registry.run_all(x + 10);
+			}
+		}));
+		let second_received = received.clone();
+		let _second_handler = my_callback.add(Box::new(move |x|
+			second_received.borrow_mut().push((2, x))
+		));
+
+		my_callback.run_all(1);
+		assert_eq!(
+			vec![
+				(1, 1), (2, 1), (1, 2), (2, 2), (1, 11), (2, 11), (1, 3), (2, 3), (1, 12), (2, 12),
+			],
+			*received.borrow());
+	}
+
+	#[test]
+	fn run_all_after_panic() {
+		let my_callback: Rc<CallbackRegistry<u32>> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(Box::new(move |x| {
+			first_received.borrow_mut().push(x);
+			assert_ne!(x, 1);
+		}));
+
+		let panicking = my_callback.clone();
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(||
+			panicking.run_all(1)
+		));
+		assert!(result.is_err());
+
+		my_callback.run_all(2);
+		assert_eq!(vec![1, 2], *received.borrow());
+	}
+
+	/// Example of a receiver with its own state.
+	#[derive(Default)]
+	struct Widget {
+		clicks: RefCell<Vec<u32>>,
+	}
+
+	#[test]
+	fn bound_callback() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let widget = Rc::new(Widget::default());
+		let other_widget = Rc::new(Widget::default());
+
+		my_callback.add_bound(&widget, Box::new(|widget: &Widget, x|
+			widget.clicks.borrow_mut().push(x)
+		));
+		my_callback.add_ref_bound(&other_widget, Box::new(|widget: &Widget, x: &u32|
+			widget.clicks.borrow_mut().push(x * 10)
+		));
+
+		my_callback.run_all(1);
+		assert_eq!(vec![1], *widget.clicks.borrow());
+		assert_eq!(vec![10], *other_widget.clicks.borrow());
+		assert_eq!(1, Rc::strong_count(&widget));
+
+		let weak_widget = Rc::downgrade(&widget);
+		drop(widget);
+		assert!(weak_widget.upgrade().is_none());
+		my_callback.run_all(2);
+		assert_eq!(vec![10, 20], *other_widget.clicks.borrow());
+		assert_eq!(1, my_callback.callbacks.borrow().len());
+	}
+
+	#[test]
+	fn isolated_panics() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+		my_callback.set_panic_policy(PanicPolicy::Isolate);
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(
+			Box::new(move |x| first_received.borrow_mut().push(x)));
+		let panicking_handler = my_callback.add(Box::new(|x| panic!("panicked with {}", x)));
+		let last_received = received.clone();
+		let _last_handler = my_callback.add(Box::new(move |x| last_received.borrow_mut().push(x)));
+
+		my_callback.run_all(1);
+		my_callback.run_all(2);
+		assert_eq!(vec![1, 1, 2, 2], *received.borrow());
+		assert!(panicking_handler.is_connected());
+
+		let panics = my_callback.take_panics();
+		assert_eq!(2, panics.len());
+		assert_eq!(panicking_handler.id(), panics[0].subscription());
+		assert_eq!("panicked with 1", panics[0].message());
+		assert_eq!("panicked with 2", panics[1].message());
+		assert!(!panics[1].is_disconnected());
+		assert!(my_callback.take_panics().is_empty());
+	}
+
+	#[test]
+	fn disconnect_repeated_panics() {
+		let my_callback: CallbackRegistry<u32, u32> = Default::default();
+		my_callback.set_panic_policy(PanicPolicy::DisconnectAfter(2));
+
+		let _first_handler = my_callback.add(Box::new(|x| x));
+		let panicking_handler = my_callback.add(Box::new(|x| {
+			assert_ne!(x, 2);
+			x * 10
+		}));
+
+		assert_eq!(vec![1, 10], my_callback.run_all_with(1, Collect));
+		assert_eq!(vec![2], my_callback.run_all_with(2, Collect));
+		assert!(panicking_handler.is_connected());
+		assert_eq!(vec![2], my_callback.run_all_with(2, Collect));
+		assert!(!panicking_handler.is_connected());
+		assert_eq!(vec![3], my_callback.run_all_with(3, Collect));
+
+		let panics = my_callback.take_panics();
+		assert_eq!(2, panics.len());
+		assert!(!panics[0].is_disconnected());
+		assert!(panics[1].is_disconnected());
+	}
+
+	#[test]
+	fn posted_callback() {
+		let my_callback: Rc<CallbackRegistry<u32>> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let registry = Rc::downgrade(&my_callback);
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(Box::new(move |x| {
+			first_received.borrow_mut().push(x);
+			registry.upgrade().unwrap().post(x + 10);
+		}));
+
+		assert_eq!(None, my_callback.post(1));
+		assert_eq!(None, my_callback.post(2));
+		assert_eq!(2, my_callback.posted_len());
+		assert!(received.borrow().is_empty());
+
+		my_callback.flush();
+		assert_eq!(vec![1, 2], *received.borrow());
+		assert_eq!(2, my_callback.posted_len());
+
+		my_callback.flush();
+		assert_eq!(vec![1, 2, 11, 12], *received.borrow());
+	}
+
+	#[test]
+	fn coalesced_posted_callback() {
+		let my_callback: CallbackRegistry<String> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(
+			Box::new(move |x| first_received.borrow_mut().push(x)));
+
+		assert_eq!(None, my_callback.post_coalesced("moved".to_string()));
+		assert_eq!(None, my_callback.post_coalesced("resized".to_string()));
+		assert_eq!(Some("moved".to_string()), my_callback.post_coalesced("moved".to_string()));
+		my_callback.flush();
+		assert_eq!(None, my_callback.post_coalesced("moved".to_string()));
+		my_callback.flush();
+		assert_eq!(vec!["moved", "resized", "moved"], *received.borrow());
+	}
+
+	#[test]
+	fn bounded_posted_callback() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(
+			Box::new(move |x| first_received.borrow_mut().push(x)));
+
+		my_callback.set_posted_limit(Some((2, OverflowPolicy::DropNewest)));
+		assert_eq!(None, my_callback.post(1));
+		assert_eq!(None, my_callback.post(2));
+		assert_eq!(Some(3), my_callback.post(3));
+		my_callback.flush();
+		assert_eq!(vec![1, 2], *received.borrow());
+
+		my_callback.set_posted_limit(Some((2, OverflowPolicy::DropOldest)));
+		assert_eq!(None, my_callback.post(4));
+		assert_eq!(None, my_callback.post(5));
+		assert_eq!(Some(4), my_callback.post(6));
+		my_callback.flush();
+		assert_eq!(vec![1, 2, 5, 6], *received.borrow());
+
+		assert_eq!(None, my_callback.post(7));
+		assert_eq!(None, my_callback.post(8));
+		my_callback.set_posted_limit(Some((1, OverflowPolicy::DropNewest)));
+		assert_eq!(1, my_callback.posted_len());
+		my_callback.set_posted_limit(None);
+		assert_eq!(None, my_callback.post(9));
+		my_callback.flush();
+		assert_eq!(vec![1, 2, 5, 6, 7, 9], *received.borrow());
+	}
+
+	#[test]
+	fn zero_capacity_posted_callback() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let _handler = my_callback.add(Box::new(|_| panic!()));
+
+		for &policy in &[OverflowPolicy::DropNewest, OverflowPolicy::DropOldest] {
+			my_callback.set_posted_limit(Some((0, policy)));
+			assert_eq!(Some(1), my_callback.post(1));
+			assert_eq!(0, my_callback.posted_len());
+		}
+		my_callback.flush();
+	}
+
+	#[test]
+	fn filtered_callback() {
+		let my_callback: CallbackRegistry<u32, u32> = Default::default();
+
+		let _all_handler = my_callback.add(Box::new(|x| x));
+		let _even_handler = my_callback.add_filtered(
+			Box::new(|x| x % 2 == 0), Box::new(|x| x * 10));
+		let _big_handler = my_callback.add_ref_filtered(
+			Box::new(|x| *x > 2), Box::new(|x| x * 100));
+
+		assert_eq!(vec![1], my_callback.run_all_with(1, Collect));
+		assert_eq!(vec![2, 20], my_callback.run_all_with(2, Collect));
+		assert_eq!(vec![3, 300], my_callback.run_all_with(3, Collect));
+		assert_eq!(vec![4, 40, 400], my_callback.run_all_with(4, Collect));
+	}
+
+	#[test]
+	fn removed_callbacks_keep_the_order() {
+		let my_callback: CallbackRegistry<u32, i32> = Default::default();
+
+		let mut handlers: Vec<_> = [0, 10, 0, -10, 10, 0, -10].iter().enumerate()
+			.map(|(i, &priority)| {
+				let value = i as i32;
+				Some(my_callback.add_with_priority(Box::new(move |_| value), priority))
+			})
+			.collect();
+		assert_eq!(vec![1, 4, 0, 2, 5, 3, 6], my_callback.run_all_with(0, Collect));
+
+		for i in &[1, 2, 6] {
+			handlers[*i] = None;
+		}
+		let _last_handler = my_callback.add_with_priority(Box::new(|_| 7), -10);
+		assert_eq!(5, my_callback.callbacks.borrow().len());
+		let _first_handler = my_callback.add_with_priority(Box::new(|_| 8), 20);
+		let _middle_handler = my_callback.add_with_priority(Box::new(|_| 9), 5);
+		assert_eq!(vec![8, 4, 9, 0, 5, 3, 7], my_callback.run_all_with(0, Collect));
+
+		handlers.clear();
+		assert_eq!(vec![8, 9, 7], my_callback.run_all_with(0, Collect));
+		assert_eq!(3, my_callback.callbacks.borrow().len());
+	}
+
+	#[test]
+	fn len_and_subscriptions() {
+		let my_callback: Rc<CallbackRegistry<u32>> = Default::default();
+		let lens = Rc::new(RefCell::new(Vec::new()));
+		assert!(my_callback.is_empty());
+
+		let registry = Rc::downgrade(&my_callback);
+		let added_handlers = Rc::new(RefCell::new(Vec::new()));
+		let adding_handlers = added_handlers.clone();
+		let adding_lens = lens.clone();
+		let first_line = line!() + 1;
+		let first_handler = my_callback.add(Box::new(move |_| {
+			let registry = registry.upgrade().unwrap();
+			adding_handlers.borrow_mut().push(registry.add(Box::new(|_| {})));
+			adding_lens.borrow_mut().push(registry.len());
+		})).with_label("adding");
+		let once_handler = my_callback.add_once(Box::new(|_| {}));
+		let widget = Rc::new(());
+		my_callback.add_bound(&widget, Box::new(|_, _| {}));
+		let second_line = line!() + 1;
+		let second_handler = my_callback.add_ref_with_priority(Box::new(|_| {}), 5)
+			.with_label("second");
+		assert_eq!(4, my_callback.len());
+		assert_eq!(Some("adding".to_string()), first_handler.label());
+
+		let subscriptions: Vec<_> = my_callback.subscriptions().collect();
+		assert_eq!(
+			vec![second_handler.id(), first_handler.id(), once_handler.id()],
+			subscriptions.iter().map(|it| it.id()).take(3).collect::<Vec<_>>());
+		assert_eq!(Some("second"), subscriptions[0].label());
+		assert_eq!(5, subscriptions[0].priority());
+		assert_eq!(second_line, subscriptions[0].location().line());
+		assert_eq!(first_line, subscriptions[1].location().line());
+		assert_eq!(file!(), subscriptions[1].location().file());
+		assert_eq!(None, subscriptions[2].label());
+		assert_eq!(0, subscriptions[2].priority());
+
+		drop(widget);
+		my_callback.run_all(1);
+		assert_eq!(vec![5], *lens.borrow());
+		assert_eq!(3, my_callback.len());
+
+		drop(second_handler);
+		assert_eq!(2, my_callback.len());
+		added_handlers.borrow_mut().clear();
+		drop(first_handler);
+		assert!(my_callback.is_empty());
+		assert_eq!(0, my_callback.subscriptions().count());
+	}
+
+	#[test]
+	fn instrumented_callback() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		my_callback.set_panic_policy(PanicPolicy::Isolate);
+
+		let _fast_handler = my_callback.add(Box::new(|_| {})).with_label("fast");
+		let _slow_handler = my_callback.add(Box::new(|x|
+			if x == 2 { thread::sleep(Duration::from_millis(5)) }
+		)).with_label("slow");
+		let _panicking_handler = my_callback.add(Box::new(|x| assert_ne!(3, x)));
+
+		my_callback.run_all(1);
+		assert!(my_callback.metrics().iter().all(|(_, stats)| stats.calls() == 0));
+
+		my_callback.set_instrumented(true);
+		for x in 1..=3 {
+			my_callback.run_all(x);
+		}
+		my_callback.set_instrumented(false);
+		my_callback.run_all(2);
+
+		let metrics = my_callback.metrics();
+		assert_eq!(
+			vec![(Some("fast"), 3, 0), (Some("slow"), 3, 0), (None, 3, 1)],
+			metrics.iter()
+				.map(|(info, stats)| (info.label(), stats.calls(), stats.panics()))
+				.collect::<Vec<_>>());
+		let (_, slow_stats) = metrics[1];
+		assert!(slow_stats.max_duration() >= Duration::from_millis(5));
+		assert!(slow_stats.total_duration() >= slow_stats.max_duration());
+		assert!(slow_stats.average_duration().unwrap() < slow_stats.max_duration());
+		assert!(metrics[0].1.max_duration() < slow_stats.max_duration());
+		assert_eq!(1, my_callback.take_panics().len());
+	}
+
+	#[test]
+	fn forward_to_parent() {
+		let parent: Rc<CallbackRegistry<String>> = Default::default();
+		let child: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let parent_received = received.clone();
+		let _parent_handler = parent.add(Box::new(move |event|
+			parent_received.borrow_mut().push(event)
+		));
+		let forward_handler = child.forward_with(&parent, |x| format!("child clicked {}", x));
+		let other_parent: Rc<CallbackRegistry<u32, bool>> = Default::default();
+		let _other_forward_handler = child.connect_to(&other_parent);
+
+		child.run_all(1);
+		drop(other_parent);
+		child.run_all(2);
+		assert_eq!(1, child.len());
+		drop(forward_handler);
+		child.run_all(3);
+		assert_eq!(vec!["child clicked 1", "child clicked 2"], *received.borrow());
+		assert!(child.is_empty());
+	}
+
+	#[test]
+	fn forwarding_cycle() {
+		let registries: Vec<Rc<CallbackRegistry<u32>>> =
+			(0..3).map(|_| Default::default()).collect();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let mut handlers: Vec<_> = registries.iter().enumerate().map(|(i, registry)| {
+			let registry_received = received.clone();
+			registry.add(Box::new(move |x| registry_received.borrow_mut().push((i, x))))
+		}).collect();
+		handlers.push(registries[0].connect_to(&registries[1]));
+		handlers.push(registries[1].connect_to(&registries[0]));
+		handlers.push(registries[1].forward_with(&registries[2], |x| x + 10));
+		handlers.push(registries[2].forward_with(&registries[0], |x| x + 100));
+
+		registries[0].run_all(1);
+		assert_eq!(vec![(0, 1), (1, 1), (2, 11)], *received.borrow());
+
+		received.borrow_mut().clear();
+		registries[2].run_all(2);
+		assert_eq!(vec![(2, 2), (0, 102), (1, 102)], *received.borrow());
+	}
 }
@@ -0,0 +1,90 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// Source of the current time, it is injected in the types depending on time so they can be
+/// driven by hand in the tests.
+pub trait Clock {
+	/// The time elapsed since the origin of the clock, it never goes backwards.
+	fn now(&self) -> Duration;
+}
+
+/// Clock which only moves when told to, it is meant for the tests.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+	now: Cell<Duration>,
+}
+
+impl ManualClock {
+	/// Creates the clock at its origin.
+	pub fn new() -> Self {
+		Default::default()
+	}
+
+	/// Moves the clock forward by `duration`.
+	pub fn advance(&self, duration: Duration) {
+		self.now.set(self.now.get() + duration);
+	}
+
+	/// Moves the clock to `now`, it panics if that is before the current time.
+	pub fn set(&self, now: Duration) {
+		assert!(now >= self.now.get(), "the clock can not go backwards");
+		self.now.set(now);
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> Duration {
+		self.now.get()
+	}
+}
+
+/// Clock following the system monotonic clock, its origin is the moment it was created.
+#[derive(Debug)]
+pub struct MonotonicClock {
+	origin: Instant,
+}
+
+impl Default for MonotonicClock {
+	fn default() -> Self {
+		Self {
+			origin: Instant::now(),
+		}
+	}
+}
+
+impl Clock for MonotonicClock {
+	fn now(&self) -> Duration {
+		self.origin.elapsed()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::clock::{Clock, ManualClock, MonotonicClock};
+	use std::time::Duration;
+
+	#[test]
+	fn manual_clock() {
+		let clock = ManualClock::new();
+		assert_eq!(Duration::ZERO, clock.now());
+
+		clock.advance(Duration::from_millis(10));
+		clock.set(Duration::from_millis(25));
+		assert_eq!(Duration::from_millis(25), clock.now());
+	}
+
+	#[test]
+	#[should_panic(expected = "backwards")]
+	fn manual_clock_backwards() {
+		let clock = ManualClock::new();
+		clock.advance(Duration::from_millis(10));
+		clock.set(Duration::from_millis(5));
+	}
+
+	#[test]
+	fn monotonic_clock() {
+		let clock = MonotonicClock::default();
+		let first = clock.now();
+		assert!(clock.now() >= first);
+	}
+}
@@ -0,0 +1,161 @@
+use std::iter::Sum as IterSum;
+
+/// Folds the values returned by the callbacks of a `callback::CallbackRegistry` into a single
+/// result.
+///
+/// The `results` iterator calls the callbacks lazily, one for each item, so a combiner that does
+/// not consume all of it prevents the remaining callbacks from running.
+pub trait Combiner<ReturnType> {
+	type Output;
+
+	/// Consumes the results of the callbacks, in the order they are executed.
+	fn combine<I: Iterator<Item=ReturnType>>(self, results: I) -> Self::Output;
+}
+
+/// Collects all the results into a `Vec`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Collect;
+
+impl<ReturnType> Combiner<ReturnType> for Collect {
+	type Output = Vec<ReturnType>;
+
+	fn combine<I: Iterator<Item=ReturnType>>(self, results: I) -> Self::Output {
+		results.collect()
+	}
+}
+
+/// Returns the first `Some` value, the callbacks after it are not executed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FirstSome;
+
+impl<T> Combiner<Option<T>> for FirstSome {
+	type Output = Option<T>;
+
+	fn combine<I: Iterator<Item=Option<T>>>(self, mut results: I) -> Self::Output {
+		results.find_map(|it| it)
+	}
+}
+
+/// Returns the value of the last callback executed, `None` if there are no callbacks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Last;
+
+impl<ReturnType> Combiner<ReturnType> for Last {
+	type Output = Option<ReturnType>;
+
+	fn combine<I: Iterator<Item=ReturnType>>(self, results: I) -> Self::Output {
+		results.last()
+	}
+}
+
+/// Returns `true` as soon as a callback returns `true`, `false` if there are no callbacks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Any;
+
+impl Combiner<bool> for Any {
+	type Output = bool;
+
+	fn combine<I: Iterator<Item=bool>>(self, mut results: I) -> Self::Output {
+		results.any(|it| it)
+	}
+}
+
+/// Returns `false` as soon as a callback returns `false`, `true` if there are no callbacks.
+///
+/// This is the combiner for veto-style queries, where any callback can deny the operation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct All;
+
+impl Combiner<bool> for All {
+	type Output = bool;
+
+	fn combine<I: Iterator<Item=bool>>(self, mut results: I) -> Self::Output {
+		results.all(|it| it)
+	}
+}
+
+/// Sums all the results.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sum;
+
+impl<ReturnType: IterSum> Combiner<ReturnType> for Sum {
+	type Output = ReturnType;
+
+	fn combine<I: Iterator<Item=ReturnType>>(self, results: I) -> Self::Output {
+		results.sum()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::callback::CallbackRegistry;
+	use crate::combiner::{
+		All,
+		Any,
+		Collect,
+		FirstSome,
+		Last,
+		Sum,
+	};
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[test]
+	fn empty_registry() {
+		let my_callback: CallbackRegistry<u32, u32> = Default::default();
+
+		assert_eq!(Vec::<u32>::new(), my_callback.run_all_with(1, Collect));
+		assert_eq!(None, my_callback.run_all_with(1, Last));
+		assert_eq!(0, my_callback.run_all_with(1, Sum));
+	}
+
+	#[test]
+	fn numeric_combiners() {
+		let my_callback: CallbackRegistry<u32, u32> = Default::default();
+
+		let _first_handler = my_callback.add(Box::new(|x| x));
+		let _second_handler = my_callback.add(Box::new(|x| x * 10));
+		my_callback.add(Box::new(|_| panic!()));
+
+		assert_eq!(vec![2, 20], my_callback.run_all_with(2, Collect));
+		assert_eq!(Some(30), my_callback.run_all_with(3, Last));
+		assert_eq!(44, my_callback.run_all_with(4, Sum));
+	}
+
+	#[test]
+	fn first_some_stops_early() {
+		let my_callback: CallbackRegistry<u32, Option<String>> = Default::default();
+		let calls = Rc::new(RefCell::new(0));
+
+		let first_calls = calls.clone();
+		let _first_handler = my_callback.add(Box::new(move |_| {
+			*first_calls.borrow_mut() += 1;
+			None
+		}));
+		let second_calls = calls.clone();
+		let _second_handler = my_callback.add(Box::new(move |x| {
+			*second_calls.borrow_mut() += 1;
+			Some(format!("second {}", x))
+		}));
+		let _third_handler = my_callback.add(Box::new(|_| panic!()));
+
+		assert_eq!(Some("second 5".to_string()), my_callback.run_all_with(5, FirstSome));
+		assert_eq!(2, *calls.borrow());
+	}
+
+	#[test]
+	fn logical_combiners() {
+		let my_callback: CallbackRegistry<u32, bool> = Default::default();
+
+		assert!(!my_callback.run_all_with(1, Any));
+		assert!(my_callback.run_all_with(1, All));
+
+		let _even_handler = my_callback.add(Box::new(|x| x % 2 == 0));
+		let _small_handler = my_callback.add(Box::new(|x| x < 10));
+
+		assert!(my_callback.run_all_with(3, Any));
+		assert!(!my_callback.run_all_with(3, All));
+		assert!(my_callback.run_all_with(4, All));
+		assert!(!my_callback.run_all_with(11, Any));
+	}
+}
@@ -0,0 +1,425 @@
+use crate::subscription::SubscriptionId;
+use std::any::Any;
+use std::time::Duration;
+
+/// What `CallbackRegistry::try_run_all` does when a callback returns an error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+	/// The dispatch stops at the first error, the remaining callbacks are not executed.
+	StopAtFirstError,
+	/// All the callbacks are executed and all the errors are collected.
+	ContinueAndCollect,
+	/// All the callbacks are executed, every error is printed to the standard error as soon as it
+	/// happens and also collected.
+	LogAndContinue,
+}
+
+/// A callback which returned an error.
+#[derive(Debug)]
+pub struct DispatchFailure<E> {
+	subscription: SubscriptionId,
+	error: E,
+}
+
+impl<E> DispatchFailure<E> {
+	/// The subscription of the callback which failed.
+	pub fn subscription(&self) -> SubscriptionId {
+		self.subscription
+	}
+
+	/// The error returned by the callback.
+	pub fn error(&self) -> &E {
+		&self.error
+	}
+
+	/// Consumes the failure returning its error.
+	pub fn into_error(self) -> E {
+		self.error
+	}
+}
+
+/// Report of a dispatch made with `CallbackRegistry::try_run_all`.
+#[derive(Debug)]
+pub struct DispatchReport<E> {
+	executed: usize,
+	failures: Vec<DispatchFailure<E>>,
+	stopped: bool,
+}
+
+impl<E> Default for DispatchReport<E> {
+	fn default() -> Self {
+		Self {
+			executed: 0,
+			failures: Vec::new(),
+			stopped: false,
+		}
+	}
+}
+
+impl<E> DispatchReport<E> {
+	/// Adds a callback which ran successfully.
+	pub(crate) fn add_success(&mut self) {
+		self.executed += 1;
+	}
+
+	/// Adds a callback which failed.
+	pub(crate) fn add_failure(&mut self, subscription: SubscriptionId, error: E) {
+		self.executed += 1;
+		self.failures.push(DispatchFailure {
+			subscription,
+			error,
+		});
+	}
+
+	/// Marks the dispatch as stopped at an error.
+	pub(crate) fn stop(&mut self) {
+		self.stopped = true;
+	}
+
+	/// The number of callbacks executed, including the ones which failed.
+	pub fn executed(&self) -> usize {
+		self.executed
+	}
+
+	/// The callbacks which failed, in the order they were executed.
+	pub fn failures(&self) -> &[DispatchFailure<E>] {
+		&self.failures
+	}
+
+	/// Consumes the report returning the callbacks which failed.
+	pub fn into_failures(self) -> Vec<DispatchFailure<E>> {
+		self.failures
+	}
+
+	/// Indicates if the dispatch stopped at an error, the callbacks after it were not executed.
+	pub fn is_stopped(&self) -> bool {
+		self.stopped
+	}
+
+	/// Indicates if none of the callbacks failed.
+	pub fn is_ok(&self) -> bool {
+		self.failures.is_empty()
+	}
+}
+
+/// How a `CallbackRegistry` handles the panics of its callbacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+	/// The panic is propagated to the caller of the dispatch, the remaining callbacks are not
+	/// executed. This is the default.
+	Propagate,
+	/// The panic is caught, the dispatch continues with the remaining callbacks.
+	Isolate,
+	/// Works like `Isolate` but a callback is disconnected once it panicked the given number of
+	/// times.
+	DisconnectAfter(u32),
+}
+
+/// A panic caught while running a callback.
+#[derive(Clone, Debug)]
+pub struct CallbackPanic {
+	subscription: SubscriptionId,
+	message: String,
+	disconnected: bool,
+}
+
+impl CallbackPanic {
+	/// Creates the report of the panic with the `payload` received from `catch_unwind`.
+	pub(crate) fn new(
+		subscription: SubscriptionId, payload: &(dyn Any + Send), disconnected: bool,
+	) -> Self {
+		let message = if let Some(message) = payload.downcast_ref::<&str>() {
+			message.to_string()
+		} else if let Some(message) = payload.downcast_ref::<String>() {
+			message.clone()
+		} else {
+			"Box<dyn Any>".to_string()
+		};
+		Self {
+			subscription,
+			message,
+			disconnected,
+		}
+	}
+
+	/// The subscription of the callback which panicked.
+	pub fn subscription(&self) -> SubscriptionId {
+		self.subscription
+	}
+
+	/// The message of the panic.
+	pub fn message(&self) -> &str {
+		&self.message
+	}
+
+	/// Indicates if the callback was disconnected because of the panic.
+	pub fn is_disconnected(&self) -> bool {
+		self.disconnected
+	}
+}
+
+/// What `CallbackRegistry::post` does when the queue is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+	/// The oldest event in the queue is discarded to make room for the new one.
+	DropOldest,
+	/// The new event is discarded.
+	DropNewest,
+}
+
+/// Metrics of a callback, they are only recorded while the instrumentation of its registry is
+/// enabled, see `CallbackRegistry::set_instrumented`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CallbackStats {
+	calls: u64,
+	total_duration: Duration,
+	max_duration: Duration,
+	errors: u64,
+	panics: u64,
+}
+
+impl CallbackStats {
+	/// Adds a call which took `duration`.
+	pub(crate) fn add_call(&mut self, duration: Duration) {
+		self.calls += 1;
+		self.total_duration += duration;
+		self.max_duration = self.max_duration.max(duration);
+	}
+
+	/// Adds a call which returned an error.
+	pub(crate) fn add_error(&mut self) {
+		self.errors += 1;
+	}
+
+	/// Adds a call which panicked.
+	pub(crate) fn add_panic(&mut self) {
+		self.panics += 1;
+	}
+
+	/// The number of times the callback was called, including the calls which failed.
+	pub fn calls(&self) -> u64 {
+		self.calls
+	}
+
+	/// The time spent in all the calls.
+	pub fn total_duration(&self) -> Duration {
+		self.total_duration
+	}
+
+	/// The time spent in the slowest call.
+	pub fn max_duration(&self) -> Duration {
+		self.max_duration
+	}
+
+	/// The average time of the calls, `None` if the callback was not called.
+	pub fn average_duration(&self) -> Option<Duration> {
+		if self.calls == 0 {
+			None
+		} else {
+			Some(self.total_duration.div_f64(self.calls as f64))
+		}
+	}
+
+	/// The number of errors returned to `CallbackRegistry::try_run_all`.
+	pub fn errors(&self) -> u64 {
+		self.errors
+	}
+
+	/// The number of panics caught, they are only caught when the panics are isolated.
+	pub fn panics(&self) -> u64 {
+		self.panics
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::callback::CallbackRegistry;
+	use crate::dispatch::ErrorPolicy;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[test]
+	fn stop_at_first_error() {
+		let my_callback: CallbackRegistry<u32, Result<(), String>> = Default::default();
+		let calls = Rc::new(RefCell::new(Vec::new()));
+
+		let first_calls = calls.clone();
+		let _first_handler = my_callback.add(Box::new(move |_| {
+			first_calls.borrow_mut().push("first");
+			Ok(())
+		}));
+		let failing_calls = calls.clone();
+		let failing_handler = my_callback.add(Box::new(move |x| {
+			failing_calls.borrow_mut().push("failing");
+			if x % 2 == 1 { Err(format!("odd {}", x)) } else { Ok(()) }
+		}));
+		let second_failing_calls = calls.clone();
+		let _second_failing_handler = my_callback.add(Box::new(move |x| {
+			second_failing_calls.borrow_mut().push("second failing");
+			if x > 2 { Err(format!("big {}", x)) } else { Ok(()) }
+		}));
+		let last_calls = calls.clone();
+		let _last_handler = my_callback.add(Box::new(move |_| {
+			last_calls.borrow_mut().push("last");
+			Ok(())
+		}));
+
+		let report = my_callback.try_run_all(3, ErrorPolicy::StopAtFirstError);
+		assert!(!report.is_ok());
+		assert!(report.is_stopped());
+		assert_eq!(2, report.executed());
+		assert_eq!(1, report.failures().len());
+		assert_eq!(failing_handler.id(), report.failures()[0].subscription());
+		assert_eq!("odd 3", report.failures()[0].error());
+		assert_eq!(vec!["first", "failing"], *calls.borrow());
+	}
+
+	#[test]
+	fn continue_and_collect() {
+		let my_callback: CallbackRegistry<u32, Result<(), String>> = Default::default();
+		let calls = Rc::new(RefCell::new(Vec::new()));
+
+		let first_calls = calls.clone();
+		let _first_handler = my_callback.add(Box::new(move |_| {
+			first_calls.borrow_mut().push("first");
+			Ok(())
+		}));
+		let failing_calls = calls.clone();
+		let failing_handler = my_callback.add(Box::new(move |x| {
+			failing_calls.borrow_mut().push("failing");
+			if x % 2 == 1 { Err(format!("odd {}", x)) } else { Ok(()) }
+		}));
+		let second_failing_calls = calls.clone();
+		let second_failing_handler = my_callback.add(Box::new(move |x| {
+			second_failing_calls.borrow_mut().push("second failing");
+			if x > 2 { Err(format!("big {}", x)) } else { Ok(()) }
+		}));
+		let last_calls = calls.clone();
+		let _last_handler = my_callback.add(Box::new(move |_| {
+			last_calls.borrow_mut().push("last");
+			Ok(())
+		}));
+
+		let report = my_callback.try_run_all(3, ErrorPolicy::ContinueAndCollect);
+		assert!(!report.is_stopped());
+		assert_eq!(4, report.executed());
+		let failures: Vec<_> = report.into_failures().into_iter()
+			.map(|it| (it.subscription(), it.into_error()))
+			.collect();
+		assert_eq!(
+			vec![
+				(failing_handler.id(), "odd 3".to_string()),
+				(second_failing_handler.id(), "big 3".to_string()),
+			],
+			failures);
+		assert_eq!(vec!["first", "failing", "second failing", "last"], *calls.borrow());
+
+		let report = my_callback.try_run_all(2, ErrorPolicy::ContinueAndCollect);
+		assert!(report.is_ok());
+		assert_eq!(4, report.executed());
+	}
+
+	#[test]
+	fn log_and_continue() {
+		let my_callback: CallbackRegistry<u32, Result<(), String>> = Default::default();
+		let calls = Rc::new(RefCell::new(Vec::new()));
+
+		let first_calls = calls.clone();
+		let _first_handler = my_callback.add(Box::new(move |_| {
+			first_calls.borrow_mut().push("first");
+			Ok(())
+		}));
+		let failing_calls = calls.clone();
+		let _failing_handler = my_callback.add(Box::new(move |x| {
+			failing_calls.borrow_mut().push("failing");
+			if x % 2 == 1 { Err(format!("odd {}", x)) } else { Ok(()) }
+		}));
+		let second_failing_calls = calls.clone();
+		let _second_failing_handler = my_callback.add(Box::new(move |x| {
+			second_failing_calls.borrow_mut().push("second failing");
+			if x > 2 { Err(format!("big {}", x)) } else { Ok(()) }
+		}));
+		let last_calls = calls.clone();
+		let _last_handler = my_callback.add(Box::new(move |_| {
+			last_calls.borrow_mut().push("last");
+			Ok(())
+		}));
+
+		let report = my_callback.try_run_all(1, ErrorPolicy::LogAndContinue);
+		assert!(!report.is_stopped());
+		assert_eq!(4, report.executed());
+		assert_eq!(1, report.failures().len());
+		assert_eq!("odd 1", report.failures()[0].error());
+	}
+
+	#[test]
+	fn errors_in_metrics() {
+		let my_callback: CallbackRegistry<u32, Result<(), String>> = Default::default();
+		let calls = Rc::new(RefCell::new(Vec::new()));
+
+		let first_calls = calls.clone();
+		let _first_handler = my_callback.add(Box::new(move |_| {
+			first_calls.borrow_mut().push("first");
+			Ok(())
+		}));
+		let failing_calls = calls.clone();
+		let _failing_handler = my_callback.add(Box::new(move |x| {
+			failing_calls.borrow_mut().push("failing");
+			if x % 2 == 1 { Err(format!("odd {}", x)) } else { Ok(()) }
+		}));
+		let second_failing_calls = calls.clone();
+		let _second_failing_handler = my_callback.add(Box::new(move |x| {
+			second_failing_calls.borrow_mut().push("second failing");
+			if x > 2 { Err(format!("big {}", x)) } else { Ok(()) }
+		}));
+		let last_calls = calls.clone();
+		let _last_handler = my_callback.add(Box::new(move |_| {
+			last_calls.borrow_mut().push("last");
+			Ok(())
+		}));
+
+		my_callback.set_instrumented(true);
+		my_callback.try_run_all(3, ErrorPolicy::ContinueAndCollect);
+		my_callback.try_run_all(1, ErrorPolicy::StopAtFirstError);
+		my_callback.run_all(3);
+		let metrics: Vec<_> = my_callback.metrics().iter()
+			.map(|(_, stats)| (stats.calls(), stats.errors()))
+			.collect();
+		assert_eq!(vec![(3, 0), (3, 2), (2, 1), (2, 0)], metrics);
+	}
+
+	#[test]
+	fn nested_run_all_during_try_run_all() {
+		let my_callback: Rc<CallbackRegistry<u32, Result<(), String>>> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+		let added_handlers = Rc::new(RefCell::new(Vec::new()));
+
+		let registry = Rc::downgrade(&my_callback);
+		let first_received = received.clone();
+		let adding_handlers = added_handlers.clone();
+		let _first_handler = my_callback.add(Box::new(move |x| {
+			first_received.borrow_mut().push(("first", x));
+			if x == 1 {
+				let registry = registry.upgrade().unwrap();
+				registry.run_all(2);
+				let added_received = first_received.clone();
+				let added = registry.add(Box::new(move |x| {
+					added_received.borrow_mut().push(("added", x));
+					Ok(())
+				}));
+				adding_handlers.borrow_mut().push(added);
+			}
+			Ok(())
+		}));
+
+		let report = my_callback.try_run_all(1, ErrorPolicy::ContinueAndCollect);
+		assert_eq!(1, report.executed());
+		assert_eq!(vec![("first", 1), ("first", 2), ("added", 2)], *received.borrow());
+		assert_eq!(2, my_callback.len());
+
+		my_callback.run_all(3);
+		assert_eq!(
+			vec![("first", 1), ("first", 2), ("added", 2), ("first", 3), ("added", 3)],
+			*received.borrow());
+	}
+}
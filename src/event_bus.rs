@@ -0,0 +1,128 @@
+use crate::callback::{CallbackRegistry, DEFAULT_PRIORITY};
+use crate::subscription::Subscription;
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// EventBus keeps one `CallbackRegistry` for each type of event.
+///
+/// Modules can communicate through strongly typed events, the ones publishing an event do not need
+/// to know about the ones subscribed to it.
+/// The events are dispatched by reference, so they do not need to implement `Clone`.
+///
+/// The registries follow the `CallbackRegistry` rules, so a callback can subscribe or publish
+/// while it is running, a nested publish of the same type of event is queued.
+#[derive(Default)]
+pub struct EventBus {
+	/// The values are `Rc<CallbackRegistry<E>>` where `E` is the type of the key.
+	/// They are behind a `Rc` so the map does not need to stay borrowed during a dispatch.
+	registries: RefCell<HashMap<TypeId, Rc<dyn Any>>>,
+}
+
+impl EventBus {
+	/// The `subscribe` method accepts a closure for the events of type `E` and returns a
+	/// `Subscription`.
+	/// Once the `Subscription` is dropped the closure will not be executed anymore.
+	#[track_caller]
+	pub fn subscribe<E: 'static>(&self, callback: Box<dyn FnMut(&E)>) -> Subscription {
+		self.subscribe_with_priority(callback, DEFAULT_PRIORITY)
+	}
+
+	/// The `subscribe_with_priority` method works like `subscribe`, see
+	/// `CallbackRegistry::add_with_priority`.
+	#[track_caller]
+	pub fn subscribe_with_priority<E: 'static>(
+		&self, callback: Box<dyn FnMut(&E)>, priority: i32,
+	) -> Subscription {
+		self.registry::<E>().add_ref_with_priority(callback, priority)
+	}
+
+	/// The `publish` method runs all the closures subscribed to the type of `event`.
+	pub fn publish<E: 'static>(&self, event: E) {
+		let registry = self.registries.borrow().get(&TypeId::of::<E>()).cloned();
+		if let Some(registry) = registry {
+			Self::downcast::<E>(registry).run_all(event);
+		}
+	}
+
+	/// The registry for the events of type `E`, it is created if needed.
+	fn registry<E: 'static>(&self) -> Rc<CallbackRegistry<E>> {
+		let registry = self.registries.borrow_mut()
+			.entry(TypeId::of::<E>())
+			.or_insert_with(|| Rc::new(CallbackRegistry::<E>::default()))
+			.clone();
+		Self::downcast(registry)
+	}
+
+	/// The registries are always stored with the `TypeId` of their events.
+	fn downcast<E: 'static>(registry: Rc<dyn Any>) -> Rc<CallbackRegistry<E>> {
+		match registry.downcast() {
+			Ok(registry) => registry,
+			Err(_) => unreachable!("registry stored with the wrong type of event"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::event_bus::EventBus;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[derive(Debug, PartialEq)]
+	struct Moved {
+		from: (u32, u32),
+		to: (u32, u32),
+	}
+
+	#[derive(Debug, PartialEq)]
+	struct Removed(u32);
+
+	#[test]
+	fn events_by_type() {
+		let bus = EventBus::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let moved_received = received.clone();
+		let _moved_subscription = bus.subscribe(Box::new(move |event: &Moved|
+			moved_received.borrow_mut().push(format!("moved to {:?}", event.to))
+		));
+		let removed_received = received.clone();
+		let _removed_subscription = bus.subscribe(Box::new(move |event: &Removed|
+			removed_received.borrow_mut().push(format!("removed {}", event.0))
+		));
+		bus.subscribe(Box::new(|_: &Removed| panic!()));
+
+		bus.publish(Moved { from: (0, 0), to: (1, 2) });
+		bus.publish(Removed(7));
+		bus.publish(3u32);
+		assert_eq!(vec!["moved to (1, 2)", "removed 7"], *received.borrow());
+	}
+
+	#[test]
+	fn publish_from_callback() {
+		let bus = Rc::new(EventBus::default());
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let inner_bus = Rc::downgrade(&bus);
+		let moved_received = received.clone();
+		let _moved_subscription = bus.subscribe(Box::new(move |event: &Moved| {
+			moved_received.borrow_mut().push(format!("moved from {:?}", event.from));
+			inner_bus.upgrade().unwrap().publish(Removed(event.from.0));
+		}));
+		let inner_bus = Rc::downgrade(&bus);
+		let removed_received = received.clone();
+		let _removed_subscription = bus.subscribe_with_priority(Box::new(move |event: &Removed| {
+			removed_received.borrow_mut().push(format!("removed {}", event.0));
+			if event.0 < 2 {
+				inner_bus.upgrade().unwrap().publish(Removed(event.0 + 1));
+			}
+		}), 1);
+
+		bus.publish(Moved { from: (0, 5), to: (1, 2) });
+		assert_eq!(
+			vec!["moved from (0, 5)", "removed 0", "removed 1", "removed 2"],
+			*received.borrow());
+	}
+}
@@ -0,0 +1,179 @@
+use crate::callback::CallbackRegistry;
+use crate::subscription::Subscription;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::mem;
+use std::rc::Rc;
+
+/// KeyedCallbackRegistry keeps the callbacks indexed by the key of the parameters they are
+/// interested in.
+///
+/// The key of each parameter is extracted with the function received on `new`, only the callbacks
+/// added for that key run, so the cost of a dispatch does not depend on the callbacks waiting for
+/// other keys.
+/// The callbacks of each key are kept in a `CallbackRegistry`, with the same rules for priorities,
+/// subscriptions and nested dispatches.
+///
+/// The registry of a key is removed once all its closures are disconnected, when the key is
+/// dispatched or by the next `prune`, which also runs from time to time when closures are added.
+/// So short lived keys, like the identifiers of objects, do not pile up.
+pub struct KeyedCallbackRegistry<KeyType, ParamType, ReturnType = ()> {
+	key: Box<dyn Fn(&ParamType) -> KeyType>,
+	/// A dispatch clones the `Rc` of its key, so a closure can add another one for any key while
+	/// it is running.
+	registries: RefCell<HashMap<KeyType, Rc<CallbackRegistry<ParamType, ReturnType>>>>,
+	/// The number of keys left by the last `prune`.
+	pruned_len: Cell<usize>,
+}
+
+impl<KeyType, ParamType, ReturnType> KeyedCallbackRegistry<KeyType, ParamType, ReturnType>
+	where KeyType: Hash + Eq {
+	/// Creates the registry using `key` to extract the key of the parameters.
+	pub fn new(key: Box<dyn Fn(&ParamType) -> KeyType>) -> Self {
+		Self {
+			key,
+			registries: RefCell::new(HashMap::new()),
+			pruned_len: Cell::new(0),
+		}
+	}
+
+	/// The `add_ref` method accepts a closure which only runs for the parameters with the given
+	/// `key`, see `CallbackRegistry::add_ref`.
+	#[track_caller]
+	pub fn add_ref(
+		&self, key: KeyType, callback: Box<dyn FnMut(&ParamType) -> ReturnType>,
+	) -> Subscription {
+		self.registry(key).add_ref(callback)
+	}
+
+	/// The `run_all` method runs the closures added for the key of `param`.
+	pub fn run_all(&self, param: ParamType) {
+		let key = (self.key)(&param);
+		let registry = self.registries.borrow().get(&key).cloned();
+		if let Some(registry) = registry {
+			registry.run_all(param);
+			if registry.is_empty() {
+				self.registries.borrow_mut().remove(&key);
+			}
+		}
+	}
+
+	/// The number of keys with closures waiting for them, it includes the keys whose closures
+	/// were all disconnected until they are removed, see `prune`.
+	pub fn keys_len(&self) -> usize {
+		self.registries.borrow().len()
+	}
+
+	/// The `prune` method removes the registries of the keys with no closure connected.
+	///
+	/// It also runs when a closure is added once the number of keys doubled since the previous
+	/// one, so the cost is spread over the additions.
+	pub fn prune(&self) {
+		let mut removed = Vec::new();
+		{
+			let mut registries = self.registries.borrow_mut();
+			for (key, registry) in mem::take(&mut *registries) {
+				if registry.is_empty() {
+					removed.push((key, registry));
+				} else {
+					registries.insert(key, registry);
+				}
+			}
+			self.pruned_len.set(registries.len());
+		}
+		// A dropped closure may hold a `Subscription` for another key, so the map is released
+		// first.
+		drop(removed);
+	}
+
+	/// The registry for the `key`, it is created if needed.
+	fn registry(&self, key: KeyType) -> Rc<CallbackRegistry<ParamType, ReturnType>> {
+		if self.keys_len() >= 2 * self.pruned_len.get().max(1) {
+			self.prune();
+		}
+		self.registries.borrow_mut().entry(key).or_default().clone()
+	}
+}
+
+impl<KeyType, ParamType, ReturnType> KeyedCallbackRegistry<KeyType, ParamType, ReturnType>
+	where KeyType: Hash + Eq, ParamType: Clone {
+	/// The `add` method accepts a closure which only runs for the parameters with the given `key`,
+	/// see `CallbackRegistry::add`.
+	#[track_caller]
+	pub fn add(
+		&self, key: KeyType, callback: Box<dyn FnMut(ParamType) -> ReturnType>,
+	) -> Subscription {
+		self.registry(key).add(callback)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::keyed_callback::KeyedCallbackRegistry;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[derive(Clone, Debug, PartialEq)]
+	struct Moved {
+		object: u32,
+		to: (u32, u32),
+	}
+
+	#[test]
+	fn keyed_callback() {
+		let my_callback = KeyedCallbackRegistry::new(Box::new(|event: &Moved| event.object));
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add(1, Box::new(move |event: Moved|
+			first_received.borrow_mut().push(format!("first {:?}", event.to))
+		));
+		let second_received = received.clone();
+		let _second_handler = my_callback.add_ref(2, Box::new(move |event: &Moved|
+			second_received.borrow_mut().push(format!("second {:?}", event.to))
+		));
+		my_callback.add(1, Box::new(|_| panic!()));
+
+		my_callback.run_all(Moved { object: 1, to: (1, 1) });
+		my_callback.run_all(Moved { object: 2, to: (2, 2) });
+		my_callback.run_all(Moved { object: 3, to: (3, 3) });
+		assert_eq!(vec!["first (1, 1)", "second (2, 2)"], *received.borrow());
+	}
+
+	#[test]
+	fn unused_keys_are_removed() {
+		let my_callback = KeyedCallbackRegistry::new(Box::new(|x: &u32| x % 10));
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let first_handler = my_callback.add(
+			1, Box::new(move |x| first_received.borrow_mut().push(x)));
+		let second_received = received.clone();
+		let _second_handler = my_callback.add(
+			2, Box::new(move |x| second_received.borrow_mut().push(x)));
+		assert_eq!(2, my_callback.keys_len());
+
+		my_callback.run_all(11);
+		drop(first_handler);
+		my_callback.run_all(21);
+		my_callback.run_all(12);
+		assert_eq!(1, my_callback.keys_len());
+		assert_eq!(vec![11, 12], *received.borrow());
+	}
+
+	#[test]
+	fn keys_never_dispatched_are_pruned() {
+		let my_callback = KeyedCallbackRegistry::new(Box::new(|x: &u32| *x));
+
+		let _kept_handler = my_callback.add(0, Box::new(|_| {}));
+		for object in 1..=1000 {
+			let handler = my_callback.add(object, Box::new(|_| panic!()));
+			drop(handler);
+			assert!(my_callback.keys_len() <= 3);
+		}
+
+		my_callback.prune();
+		assert_eq!(1, my_callback.keys_len());
+	}
+}
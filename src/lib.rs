@@ -1,4 +1,18 @@
+pub mod async_callback;
 pub mod callback;
+pub mod clock;
+pub mod combiner;
+pub mod dispatch;
+pub mod event_bus;
 pub mod extension;
+pub mod keyed_callback;
 pub mod object;
+pub mod observable;
+pub mod rate_limit;
+pub mod reactive;
+pub mod scheduler;
 pub mod shared;
+pub mod slab;
+pub mod subscription;
+pub mod sync_callback;
+pub mod tuple_callback;
@@ -0,0 +1,114 @@
+use crate::callback::CallbackRegistry;
+use crate::subscription::Subscription;
+use std::cell::RefCell;
+
+/// A closure receiving the old and the new values.
+type ChangeCallback<T> = Box<dyn FnMut(&T, &T)>;
+
+/// Observable holds a value and notifies its subscribers every time the value changes.
+///
+/// The subscribers receive the old and the new values, they are only notified when the new value
+/// is different from the old one.
+/// The notifications follow the `CallbackRegistry` rules, so a subscriber can change the value
+/// while it is running, the notification for that change is queued.
+#[derive(Default)]
+pub struct Observable<T> {
+	value: RefCell<T>,
+	/// Receives the old and the new values.
+	changed: CallbackRegistry<(T, T)>,
+}
+
+impl<T: Clone + PartialEq + 'static> Observable<T> {
+	/// Creates the observable with the initial `value`.
+	pub fn new(value: T) -> Self {
+		Self {
+			value: RefCell::new(value),
+			changed: Default::default(),
+		}
+	}
+
+	/// A copy of the current value.
+	pub fn get(&self) -> T {
+		self.value.borrow().clone()
+	}
+
+	/// Changes the value, the subscribers are notified if it is different from the current one.
+	pub fn set(&self, value: T) {
+		if *self.value.borrow() == value {
+			return;
+		}
+		let old = self.value.replace(value);
+		self.notify(old);
+	}
+
+	/// Changes the value in place with `f`, the subscribers are notified if it changed.
+	pub fn update(&self, f: impl FnOnce(&mut T)) {
+		let old = self.get();
+		f(&mut self.value.borrow_mut());
+		if *self.value.borrow() != old {
+			self.notify(old);
+		}
+	}
+
+	/// The `subscribe` method accepts a closure receiving the old and the new values and returns a
+	/// `Subscription`.
+	/// Once the `Subscription` is dropped the closure will not be executed anymore.
+	#[track_caller]
+	pub fn subscribe(&self, mut callback: ChangeCallback<T>) -> Subscription {
+		self.changed.add_ref(Box::new(move |(old, new)| callback(old, new)))
+	}
+
+	/// Notifies the subscribers that the value changed from `old`.
+	fn notify(&self, old: T) {
+		let new = self.get();
+		self.changed.run_all((old, new));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::observable::Observable;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[test]
+	fn notify_on_change() {
+		let name = Observable::new("first".to_string());
+		let changes = Rc::new(RefCell::new(Vec::new()));
+
+		let name_changes = changes.clone();
+		let subscription = name.subscribe(Box::new(move |old, new|
+			name_changes.borrow_mut().push(format!("{} -> {}", old, new))
+		));
+
+		name.set("first".to_string());
+		name.set("second".to_string());
+		name.update(|name| name.push_str(" name"));
+		name.update(|_| {});
+		assert_eq!("second name", name.get());
+
+		drop(subscription);
+		name.set("third".to_string());
+		assert_eq!(vec!["first -> second", "second -> second name"], *changes.borrow());
+		assert_eq!("third", name.get());
+	}
+
+	#[test]
+	fn set_from_subscriber() {
+		let counter: Rc<Observable<u32>> = Default::default();
+		let changes = Rc::new(RefCell::new(Vec::new()));
+
+		let inner_counter = Rc::downgrade(&counter);
+		let counter_changes = changes.clone();
+		let _subscription = counter.subscribe(Box::new(move |old, new| {
+			counter_changes.borrow_mut().push((*old, *new));
+			if *new < 3 {
+				inner_counter.upgrade().unwrap().update(|it| *it += 1);
+			}
+		}));
+
+		counter.set(1);
+		assert_eq!(vec![(0, 1), (1, 2), (2, 3)], *changes.borrow());
+		assert_eq!(3, counter.get());
+	}
+}
@@ -0,0 +1,361 @@
+use crate::callback::CallbackRegistry;
+use crate::scheduler::Scheduler;
+use crate::subscription::{Subscription, SubscriptionState};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::{Rc, Weak};
+use std::time::Duration;
+
+/// The edges of a burst of values on which a rate limited closure runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+	/// With the first value of the burst, the other values are dropped.
+	Leading,
+	/// With the last value of the burst, once it is over.
+	Trailing,
+	/// With the first value and with the last one, if there was more than one value.
+	Both,
+}
+
+impl Edge {
+	fn is_leading(self) -> bool {
+		self != Edge::Trailing
+	}
+
+	fn is_trailing(self) -> bool {
+		self != Edge::Leading
+	}
+}
+
+/// How a `Limiter` decides when to run its closure.
+#[derive(Clone, Copy)]
+enum Mode {
+	/// The burst lasts until no value was received for the `Duration`.
+	Debounce(Duration, Edge),
+	/// The burst lasts the `Duration` from its first value, then a new one starts.
+	Throttle(Duration, Edge),
+	/// The last value is passed every `Duration`, if there is a new one.
+	Sample(Duration),
+}
+
+/// The state of a rate limited closure, it is owned by the closure added to the registry so it
+/// goes away with it. The timers only keep a `Weak` reference to it.
+struct Limiter<ParamType> {
+	mode: Mode,
+	scheduler: Rc<Scheduler>,
+	callback: RefCell<Box<dyn FnMut(ParamType)>>,
+	/// The last value received which was not passed to the closure yet.
+	pending: RefCell<Option<ParamType>>,
+	/// The timer ending the current burst, `None` outside of a burst. It is the periodic timer for
+	/// `Mode::Sample`.
+	timer: RefCell<Option<Subscription>>,
+	/// The state of the subscription returned by the registry, the timers check it because the
+	/// registry only drops the disconnected closures on its next `run_all`.
+	subscription: RefCell<Weak<SubscriptionState>>,
+	/// Indicates if the closure is running.
+	calling: Cell<bool>,
+	/// The values passed while the closure is running, they are passed once it returns.
+	queued: RefCell<VecDeque<ParamType>>,
+}
+
+impl<ParamType: 'static> Limiter<ParamType> {
+	/// Receives a value from the registry.
+	fn receive(self: &Rc<Self>, value: ParamType) {
+		match self.mode {
+			Mode::Debounce(wait, edge) => {
+				let starts = self.timer.borrow().is_none();
+				self.start_timer(wait);
+				self.push(starts, edge, value);
+			}
+			Mode::Throttle(interval, edge) => {
+				let starts = self.timer.borrow().is_none();
+				if starts {
+					self.start_timer(interval);
+				}
+				self.push(starts, edge, value);
+			}
+			Mode::Sample(_) => {
+				self.pending.replace(Some(value));
+			}
+		}
+	}
+
+	/// Passes the `value` to the closure if it `starts` a burst on a leading `edge`, otherwise it
+	/// is kept for the trailing one.
+	fn push(&self, starts: bool, edge: Edge, value: ParamType) {
+		if starts && edge.is_leading() {
+			self.call(value);
+		} else if edge.is_trailing() {
+			self.pending.replace(Some(value));
+		}
+	}
+
+	/// Ends the current burst, or the current period for `Mode::Sample`.
+	fn end(self: &Rc<Self>) {
+		if let Mode::Debounce(..) | Mode::Throttle(..) = self.mode {
+			self.timer.replace(None);
+		}
+		let value = self.pending.borrow_mut().take();
+		let value = match value {
+			Some(value) if self.is_active() => value,
+			_ => return,
+		};
+		if let Mode::Throttle(interval, _) = self.mode {
+			// The trailing value starts a new burst, so the closure does not run twice in a row.
+			self.start_timer(interval);
+		}
+		self.call(value);
+	}
+
+	/// Starts the timer passing the last value every `period`.
+	fn start_sampling(self: &Rc<Self>, period: Duration) {
+		let timer = self.scheduler.every(period, Box::new(self.end_timer()));
+		self.timer.replace(Some(timer));
+	}
+
+	/// Starts the timer ending the current burst after `delay`, replacing the previous one.
+	fn start_timer(self: &Rc<Self>, delay: Duration) {
+		let timer = self.scheduler.after(delay, Box::new(self.end_timer()));
+		self.timer.replace(Some(timer));
+	}
+
+	/// The closure of the timers, it calls `end` while the limiter is alive.
+	fn end_timer(self: &Rc<Self>) -> impl FnMut() {
+		let limiter = Rc::downgrade(self);
+		move || {
+			if let Some(limiter) = limiter.upgrade() {
+				limiter.end();
+			}
+		}
+	}
+
+	/// Indicates if the subscription of the closure is connected and not blocked.
+	fn is_active(&self) -> bool {
+		self.subscription.borrow().upgrade()
+			.is_some_and(|state| state.is_connected() && !state.is_blocked())
+	}
+
+	/// Runs the closure with `value`, it is queued if the closure is already running.
+	fn call(&self, value: ParamType) {
+		if self.calling.replace(true) {
+			self.queued.borrow_mut().push_back(value);
+			return;
+		}
+		let _calling = Calling(self);
+		let mut value = Some(value);
+		while let Some(current) = value {
+			(self.callback.borrow_mut())(current);
+			value = self.queued.borrow_mut().pop_front();
+		}
+	}
+}
+
+/// Marks the closure of a `Limiter` as not running once it is dropped, even if the closure
+/// panics. The values queued are discarded in that case, they were meant to follow the value
+/// which panicked.
+struct Calling<'a, ParamType>(&'a Limiter<ParamType>);
+
+impl<'a, ParamType> Drop for Calling<'a, ParamType> {
+	fn drop(&mut self) {
+		self.0.calling.set(false);
+		self.0.queued.take();
+	}
+}
+
+impl<ParamType: Clone + 'static> CallbackRegistry<ParamType> {
+	/// The `add_debounced` method accepts a closure which runs once per burst of values, a burst
+	/// lasts until no value was received for `wait`.
+	///
+	/// The `edge` tells if the closure receives the first value of the burst, right away, or the
+	/// last one once the burst is over. The bursts are timed by the `scheduler`, so the trailing
+	/// values are passed by its `tick`, unless the closure is blocked or disconnected by then.
+	#[track_caller]
+	pub fn add_debounced(
+		&self, scheduler: &Rc<Scheduler>, wait: Duration, edge: Edge,
+		callback: Box<dyn FnMut(ParamType)>,
+	) -> Subscription {
+		self.add_limited(scheduler, Mode::Debounce(wait, edge), callback)
+	}
+
+	/// The `add_throttled` method accepts a closure which runs at most once per `interval`, see
+	/// `add_debounced`.
+	///
+	/// A burst starts with the first value and lasts `interval`, the trailing value starts a new
+	/// one, so the closure runs at a steady rate while the values keep coming.
+	#[track_caller]
+	pub fn add_throttled(
+		&self, scheduler: &Rc<Scheduler>, interval: Duration, edge: Edge,
+		callback: Box<dyn FnMut(ParamType)>,
+	) -> Subscription {
+		self.add_limited(scheduler, Mode::Throttle(interval, edge), callback)
+	}
+
+	/// The `add_sampled` method accepts a closure which receives the last value every `period`,
+	/// as long as a value was received since the previous one, see `add_debounced`.
+	#[track_caller]
+	pub fn add_sampled(
+		&self, scheduler: &Rc<Scheduler>, period: Duration, callback: Box<dyn FnMut(ParamType)>,
+	) -> Subscription {
+		self.add_limited(scheduler, Mode::Sample(period), callback)
+	}
+
+	/// Adds the closure rate limited according to `mode`.
+	#[track_caller]
+	fn add_limited(
+		&self, scheduler: &Rc<Scheduler>, mode: Mode, callback: Box<dyn FnMut(ParamType)>,
+	) -> Subscription {
+		let limiter = Rc::new(Limiter {
+			mode,
+			scheduler: scheduler.clone(),
+			callback: RefCell::new(callback),
+			pending: RefCell::new(None),
+			timer: RefCell::new(None),
+			subscription: RefCell::new(Weak::new()),
+			calling: Cell::new(false),
+			queued: RefCell::new(VecDeque::new()),
+		});
+		if let Mode::Sample(period) = mode {
+			limiter.start_sampling(period);
+		}
+		let receiver = limiter.clone();
+		let subscription = self.add(Box::new(move |value| receiver.receive(value)));
+		limiter.subscription.replace(Rc::downgrade(subscription.state()));
+		subscription
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::callback::CallbackRegistry;
+	use crate::clock::ManualClock;
+	use crate::dispatch::PanicPolicy;
+	use crate::rate_limit::Edge;
+	use crate::scheduler::Scheduler;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use std::time::Duration;
+
+	#[test]
+	fn debounce() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Rc::new(Scheduler::new(clock.clone()));
+		let wait = Duration::from_millis(25);
+
+		for &(edge, expected) in &[
+			(Edge::Leading, &[1, 5][..]),
+			(Edge::Trailing, &[3, 6][..]),
+			(Edge::Both, &[1, 3, 5, 6][..]),
+		] {
+			let my_callback: CallbackRegistry<u32> = Default::default();
+			let received = Rc::new(RefCell::new(Vec::new()));
+			let debounced_received = received.clone();
+			let _debounced_handler = my_callback.add_debounced(
+				&scheduler, wait, edge, Box::new(move |value|
+					debounced_received.borrow_mut().push(value)
+				));
+
+			for value in 1..=3 {
+				clock.advance(Duration::from_millis(10));
+				scheduler.tick();
+				my_callback.run_all(value);
+			}
+			clock.advance(Duration::from_secs(1));
+			scheduler.tick();
+			for value in 5..=6 {
+				clock.advance(Duration::from_millis(10));
+				scheduler.tick();
+				my_callback.run_all(value);
+			}
+			clock.advance(Duration::from_secs(1));
+			scheduler.tick();
+			assert_eq!(expected, &received.borrow()[..], "{:?}", edge);
+		}
+	}
+
+	#[test]
+	fn throttle() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Rc::new(Scheduler::new(clock.clone()));
+		let interval = Duration::from_millis(25);
+
+		for &(edge, expected) in &[
+			(Edge::Leading, &[1, 4][..]),
+			(Edge::Trailing, &[3, 5][..]),
+			(Edge::Both, &[1, 3, 5][..]),
+		] {
+			let my_callback: CallbackRegistry<u32> = Default::default();
+			let received = Rc::new(RefCell::new(Vec::new()));
+			let throttled_received = received.clone();
+			let _throttled_handler = my_callback.add_throttled(
+				&scheduler, interval, edge, Box::new(move |value|
+					throttled_received.borrow_mut().push(value)
+				));
+
+			for value in 1..=5 {
+				clock.advance(Duration::from_millis(10));
+				scheduler.tick();
+				my_callback.run_all(value);
+			}
+			clock.advance(Duration::from_secs(1));
+			scheduler.tick();
+			assert_eq!(expected, &received.borrow()[..], "{:?}", edge);
+		}
+	}
+
+	#[test]
+	fn sample_and_cancel() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Rc::new(Scheduler::new(clock.clone()));
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let sampled_received = received.clone();
+		let sampled_handler = my_callback.add_sampled(
+			&scheduler, Duration::from_millis(25), Box::new(move |value|
+				sampled_received.borrow_mut().push(value)
+			));
+
+		for value in 1..=5 {
+			clock.advance(Duration::from_millis(10));
+			scheduler.tick();
+			my_callback.run_all(value);
+		}
+		clock.advance(Duration::from_secs(1));
+		scheduler.tick();
+		assert_eq!(vec![2, 4, 5], *received.borrow());
+
+		my_callback.run_all(6);
+		drop(sampled_handler);
+		clock.advance(Duration::from_secs(1));
+		scheduler.tick();
+		assert_eq!(vec![2, 4, 5], *received.borrow());
+
+		// The timer goes away with the closure, once the registry removes it.
+		my_callback.run_all(7);
+		assert_eq!(None, scheduler.next_due());
+	}
+
+	#[test]
+	fn isolated_panic() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Rc::new(Scheduler::new(clock.clone()));
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		my_callback.set_panic_policy(PanicPolicy::Isolate);
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let throttled_received = received.clone();
+		let _throttled_handler = my_callback.add_throttled(
+			&scheduler, Duration::from_millis(5), Edge::Leading, Box::new(move |value| {
+				assert_ne!(1, value);
+				throttled_received.borrow_mut().push(value);
+			}));
+
+		for value in 1..=4 {
+			clock.advance(Duration::from_millis(10));
+			scheduler.tick();
+			my_callback.run_all(value);
+		}
+		assert_eq!(vec![2, 3, 4], *received.borrow());
+		assert_eq!(1, my_callback.take_panics().len());
+	}
+}
@@ -0,0 +1,281 @@
+use crate::callback::CallbackRegistry;
+use crate::subscription::Subscription;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+/// A source of values which can be composed with combinators, each one of them produces a
+/// `Derived` registry which closures can be added to.
+///
+/// A source does not keep the registries derived from it alive, once a `Derived` is dropped its
+/// closures in the sources are disconnected.
+/// A `Derived` keeps the registries it was derived from alive, so a chain like
+/// `registry.map(f).filter(g)` keeps working after the intermediate registry is dropped.
+pub trait Source<T: 'static> {
+	/// Adds a closure receiving the values of the source.
+	fn subscribe(&self, callback: Box<dyn FnMut(&T)>) -> Subscription;
+
+	/// What a registry derived from the source has to keep alive, `None` if the source is owned by
+	/// someone else.
+	fn keep_alive(&self) -> Option<Rc<dyn Any>> {
+		None
+	}
+
+	/// The `map` method derives a registry receiving the values transformed by `f`.
+	fn map<U: 'static>(&self, mut f: impl FnMut(&T) -> U + 'static) -> Derived<U>
+		where Self: Sized {
+		Derived::new(self.keep_alive().into_iter().collect(), |registry| vec![
+			self.subscribe(forward(registry, move |registry, value| registry.run_all(f(value)))),
+		])
+	}
+
+	/// The `filter` method derives a registry receiving only the values accepted by `predicate`.
+	fn filter(&self, mut predicate: impl FnMut(&T) -> bool + 'static) -> Derived<T>
+		where Self: Sized, T: Clone {
+		Derived::new(self.keep_alive().into_iter().collect(), |registry| vec![
+			self.subscribe(forward(registry, move |registry, value| {
+				if predicate(value) {
+					registry.run_all(value.clone());
+				}
+			})),
+		])
+	}
+
+	/// The `scan` method derives a registry receiving the accumulated value, it is updated by `f`
+	/// with every value of the source, starting from `initial`.
+	fn scan<A: Clone + 'static>(
+		&self, initial: A, mut f: impl FnMut(&A, &T) -> A + 'static,
+	) -> Derived<A> where Self: Sized {
+		let mut accumulated = initial;
+		Derived::new(self.keep_alive().into_iter().collect(), |registry| vec![
+			self.subscribe(forward(registry, move |registry, value| {
+				accumulated = f(&accumulated, value);
+				registry.run_all(accumulated.clone());
+			})),
+		])
+	}
+
+	/// The `distinct_until_changed` method derives a registry which skips the values equal to the
+	/// previous one.
+	fn distinct_until_changed(&self) -> Derived<T> where Self: Sized, T: Clone + PartialEq {
+		let mut last = None;
+		Derived::new(self.keep_alive().into_iter().collect(), |registry| vec![
+			self.subscribe(forward(registry, move |registry, value: &T| {
+				if last.as_ref() != Some(value) {
+					last = Some(value.clone());
+					registry.run_all(value.clone());
+				}
+			})),
+		])
+	}
+
+	/// The `take` method derives a registry receiving only the first `count` values.
+	fn take(&self, count: usize) -> Derived<T> where Self: Sized, T: Clone {
+		let mut remaining = count;
+		Derived::new(self.keep_alive().into_iter().collect(), |registry| vec![
+			self.subscribe(forward(registry, move |registry, value: &T| {
+				if remaining > 0 {
+					remaining -= 1;
+					registry.run_all(value.clone());
+				}
+			})),
+		])
+	}
+
+	/// The `merge` method derives a registry receiving the values of both sources.
+	fn merge(&self, other: &impl Source<T>) -> Derived<T> where Self: Sized, T: Clone {
+		let parents = self.keep_alive().into_iter().chain(other.keep_alive()).collect();
+		Derived::new(parents, |registry| vec![
+			self.subscribe(forward(
+				registry.clone(), |registry, value: &T| registry.run_all(value.clone()))),
+			other.subscribe(forward(
+				registry, |registry, value: &T| registry.run_all(value.clone()))),
+		])
+	}
+
+	/// The `zip` method derives a registry receiving pairs with a value of each source, the values
+	/// are paired in the order they arrive and wait until the other source produces its own.
+	fn zip<U: Clone + 'static>(&self, other: &impl Source<U>) -> Derived<(T, U)>
+		where Self: Sized, T: Clone {
+		let parents = self.keep_alive().into_iter().chain(other.keep_alive()).collect();
+		let pending = Rc::new(RefCell::new((VecDeque::new(), VecDeque::new())));
+		let other_pending = pending.clone();
+		Derived::new(parents, |registry| vec![
+			self.subscribe(forward(registry.clone(), move |registry, value: &T| {
+				let pair = {
+					let (values, other_values) = &mut *pending.borrow_mut();
+					match other_values.pop_front() {
+						Some(other_value) => Some((value.clone(), other_value)),
+						None => {
+							values.push_back(value.clone());
+							None
+						}
+					}
+				};
+				if let Some(pair) = pair {
+					registry.run_all(pair);
+				}
+			})),
+			other.subscribe(forward(registry, move |registry, other_value: &U| {
+				let pair = {
+					let (values, other_values) = &mut *other_pending.borrow_mut();
+					match values.pop_front() {
+						Some(value) => Some((value, other_value.clone())),
+						None => {
+							other_values.push_back(other_value.clone());
+							None
+						}
+					}
+				};
+				if let Some(pair) = pair {
+					registry.run_all(pair);
+				}
+			})),
+		])
+	}
+}
+
+impl<T: 'static> Source<T> for CallbackRegistry<T> {
+	fn subscribe(&self, callback: Box<dyn FnMut(&T)>) -> Subscription {
+		self.add_ref(callback)
+	}
+}
+
+/// A registry fed by a combinator, see `Source`.
+///
+/// It dereferences to its `CallbackRegistry`, so closures are added to it as usual.
+pub struct Derived<T> {
+	registry: Rc<CallbackRegistry<T>>,
+	/// It is shared with the registries derived from this one.
+	upstream: Rc<Upstream>,
+}
+
+/// The connection of a `Derived` to its sources.
+struct Upstream {
+	/// The registry of the `Derived`, so the registries derived from it keep it alive.
+	_registry: Rc<dyn Any>,
+	_subscriptions: Vec<Subscription>,
+	/// The sources which are also derived registries.
+	_parents: Vec<Rc<dyn Any>>,
+}
+
+impl<T: 'static> Derived<T> {
+	/// Creates the registry, `connect` subscribes to the sources forwarding their values to it.
+	fn new(
+		parents: Vec<Rc<dyn Any>>,
+		connect: impl FnOnce(Weak<CallbackRegistry<T>>) -> Vec<Subscription>,
+	) -> Self {
+		let registry = Rc::new(CallbackRegistry::default());
+		let subscriptions = connect(Rc::downgrade(&registry));
+		Self {
+			registry: registry.clone(),
+			upstream: Rc::new(Upstream {
+				_registry: registry,
+				_subscriptions: subscriptions,
+				_parents: parents,
+			}),
+		}
+	}
+}
+
+impl<T> Deref for Derived<T> {
+	type Target = CallbackRegistry<T>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.registry
+	}
+}
+
+impl<T: 'static> Source<T> for Derived<T> {
+	fn subscribe(&self, callback: Box<dyn FnMut(&T)>) -> Subscription {
+		self.registry.add_ref(callback)
+	}
+
+	fn keep_alive(&self) -> Option<Rc<dyn Any>> {
+		Some(self.upstream.clone())
+	}
+}
+
+/// Creates the closure added to a source, `f` receives the derived registry while it is alive.
+fn forward<T, U: 'static>(
+	registry: Weak<CallbackRegistry<U>>, mut f: impl FnMut(&CallbackRegistry<U>, &T) + 'static,
+) -> Box<dyn FnMut(&T)> {
+	Box::new(move |value| {
+		if let Some(registry) = registry.upgrade() {
+			f(&registry, value);
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::callback::CallbackRegistry;
+	use crate::reactive::Source;
+	use std::cell::RefCell;
+	use std::fmt::Debug;
+	use std::rc::Rc;
+
+	/// Adds a closure to `source` saving the values it receives.
+	fn record<T: Clone + 'static>(source: &impl Source<T>) -> (Rc<RefCell<Vec<T>>>, impl Debug) {
+		let received = Rc::new(RefCell::new(Vec::new()));
+		let source_received = received.clone();
+		let subscription = source.subscribe(Box::new(move |value: &T|
+			source_received.borrow_mut().push(value.clone())
+		));
+		(received, subscription)
+	}
+
+	#[test]
+	fn map_and_filter() {
+		let source: CallbackRegistry<u32> = Default::default();
+		let derived = source.map(|x| x * 10).filter(|x| x % 20 == 0);
+		let (received, _subscription) = record(&derived);
+
+		for x in 1..=4 {
+			source.run_all(x);
+		}
+		assert_eq!(vec![20, 40], *received.borrow());
+
+		drop(derived);
+		source.run_all(6);
+		assert_eq!(vec![20, 40], *received.borrow());
+		assert!(source.is_empty());
+	}
+
+	#[test]
+	fn scan_distinct_and_take() {
+		let source: CallbackRegistry<u32> = Default::default();
+		let sum = source.scan(0, |sum, x| sum + x);
+		let (sums, _sum_subscription) = record(&sum);
+		let distinct = source.distinct_until_changed().take(3);
+		let (distinct_values, _distinct_subscription) = record(&distinct);
+
+		for &x in &[1, 1, 2, 2, 2, 0, 3] {
+			source.run_all(x);
+		}
+		assert_eq!(vec![1, 2, 4, 6, 8, 8, 11], *sums.borrow());
+		assert_eq!(vec![1, 2, 0], *distinct_values.borrow());
+	}
+
+	#[test]
+	fn merge_and_zip() {
+		let numbers: CallbackRegistry<u32> = Default::default();
+		let names: CallbackRegistry<&'static str> = Default::default();
+		let more_numbers: CallbackRegistry<u32> = Default::default();
+		let merged = numbers.merge(&more_numbers.map(|x| x + 100));
+		let (merged_values, _merged_subscription) = record(&merged);
+		let zipped = merged.zip(&names);
+		let (pairs, _zipped_subscription) = record(&zipped);
+
+		numbers.run_all(1);
+		more_numbers.run_all(2);
+		names.run_all("first");
+		names.run_all("second");
+		names.run_all("third");
+		numbers.run_all(3);
+		assert_eq!(vec![1, 102, 3], *merged_values.borrow());
+		assert_eq!(vec![(1, "first"), (102, "second"), (3, "third")], *pairs.borrow());
+	}
+}
@@ -0,0 +1,537 @@
+use crate::clock::{Clock, MonotonicClock};
+use crate::subscription::{Subscription, SubscriptionState};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::mem;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// The closure of a timer.
+enum TimerCallback {
+	/// Fired once, then the closure is taken out leaving `None` and the timer is disconnected.
+	Once(Option<Box<dyn FnOnce()>>),
+	/// Fired every `interval`.
+	Repeated(Box<dyn FnMut()>, Duration),
+}
+
+/// A closure waiting for its time in a `Scheduler`.
+struct Timer {
+	due: Duration,
+	/// The order in which the timers were added, the ones due at the same time fire in that order.
+	sequence: u64,
+	callback: TimerCallback,
+	subscription: Rc<SubscriptionState>,
+}
+
+/// The timers are ordered by the time they are due, the earliest is the greatest so it is at the
+/// top of the `BinaryHeap`.
+impl Ord for Timer {
+	fn cmp(&self, other: &Self) -> Ordering {
+		(other.due, other.sequence).cmp(&(self.due, self.sequence))
+	}
+}
+
+impl PartialOrd for Timer {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl PartialEq for Timer {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Timer {}
+
+impl Timer {
+	/// Indicates if it is a one-shot timer waiting to be unblocked.
+	fn is_pending_once(&self) -> bool {
+		matches!(self.callback, TimerCallback::Once(_)) && self.subscription.is_blocked()
+	}
+}
+
+/// The subscription is disconnected when the timer is dropped, including when the scheduler
+/// itself is dropped.
+impl Drop for Timer {
+	fn drop(&mut self) {
+		self.subscription.disconnect();
+	}
+}
+
+/// Scheduler fires closures after a delay, at intervals or at a given time of its `Clock`, or on a
+/// given `tick`.
+///
+/// It does not have a thread of its own, the closures are fired by `tick`, which is meant to be
+/// called regularly, for instance on every frame of the application.
+///
+/// The closures are added like on a `CallbackManager`, they return a `Subscription` and are
+/// cancelled once the `Subscription` is dropped. A blocked interval is skipped when it is due, a
+/// blocked one-shot closure stays pending until it is unblocked.
+pub struct Scheduler {
+	clock: Rc<dyn Clock>,
+	/// A cancelled timer stays in the heap until it is due or until the next `compact`.
+	timers: RefCell<BinaryHeap<Timer>>,
+	/// The timers waiting for a tick, by the number of the tick and their sequence. They are moved
+	/// to `timers` when their tick starts.
+	tick_timers: RefCell<BTreeMap<(u64, u64), Timer>>,
+	/// The number of the last tick, see `ticks`.
+	ticks: Cell<u64>,
+	/// The number of timers left by the last `compact`.
+	compacted_len: Cell<usize>,
+	/// The sequence of the next timer added.
+	sequence: Cell<u64>,
+	/// Indicates if the timers are being fired.
+	ticking: Cell<bool>,
+}
+
+/// Uses a `MonotonicClock`.
+impl Default for Scheduler {
+	fn default() -> Self {
+		Self::new(Rc::new(MonotonicClock::default()))
+	}
+}
+
+impl Scheduler {
+	/// Creates the scheduler driven by `clock`.
+	pub fn new(clock: Rc<dyn Clock>) -> Self {
+		Self {
+			clock,
+			timers: RefCell::new(BinaryHeap::new()),
+			tick_timers: RefCell::new(BTreeMap::new()),
+			ticks: Cell::new(0),
+			compacted_len: Cell::new(0),
+			sequence: Cell::new(0),
+			ticking: Cell::new(false),
+		}
+	}
+
+	/// The current time of the clock.
+	pub fn now(&self) -> Duration {
+		self.clock.now()
+	}
+
+	/// The `after` method accepts a closure which fires once, on the first `tick` after `delay`.
+	pub fn after(&self, delay: Duration, callback: Box<dyn FnOnce()>) -> Subscription {
+		self.at(self.now() + delay, callback)
+	}
+
+	/// The `at` method accepts a closure which fires once, on the first `tick` once the clock
+	/// reaches `time`.
+	pub fn at(&self, time: Duration, callback: Box<dyn FnOnce()>) -> Subscription {
+		self.insert(time, TimerCallback::Once(Some(callback)))
+	}
+
+	/// The `every` method accepts a closure which fires every `interval`, starting one `interval`
+	/// from now.
+	///
+	/// When a `tick` happens after more than one interval the closure fires once for each one of
+	/// them.
+	pub fn every(&self, interval: Duration, callback: Box<dyn FnMut()>) -> Subscription {
+		assert!(interval > Duration::ZERO, "the interval of a timer can not be zero");
+		self.insert(self.now() + interval, TimerCallback::Repeated(callback, interval))
+	}
+
+	/// The number of the last `tick`, the first one is 1. The calls made from the closures are not
+	/// counted, they do nothing.
+	pub fn ticks(&self) -> u64 {
+		self.ticks.get()
+	}
+
+	/// The `on_tick` method accepts a closure which fires once, on the `tick` with the given
+	/// number, see `ticks`. When that tick already happened the closure fires on the next one.
+	pub fn on_tick(&self, tick: u64, callback: Box<dyn FnOnce()>) -> Subscription {
+		// The timer is moved to the heap once its tick starts, it is already due by then.
+		let callback = TimerCallback::Once(Some(callback));
+		let (timer, subscription) = self.timer(Duration::ZERO, callback);
+		self.tick_timers.borrow_mut().insert((tick, timer.sequence), timer);
+		subscription
+	}
+
+	/// The `after_ticks` method accepts a closure which fires once, `ticks` ticks from now. When
+	/// `ticks` is 0 it fires on the next one, like when it is 1.
+	pub fn after_ticks(&self, ticks: u64, callback: Box<dyn FnOnce()>) -> Subscription {
+		self.on_tick(self.ticks() + ticks, callback)
+	}
+
+	/// The `tick` method fires the closures which are due, in the order of their time.
+	///
+	/// A closure added by another one during a `tick` waits for the next one, even if it is
+	/// already due. Calling `tick` from one of the closures does nothing, the current `tick` is
+	/// already firing the closures which are due.
+	///
+	/// When a closure panics the panic goes through `tick`, the timers which were not fired yet
+	/// stay in the scheduler for the next one, a panicking interval included.
+	pub fn tick(&self) {
+		let mut tick = match Tick::start(self) {
+			Some(tick) => tick,
+			None => return,
+		};
+		let ticks = self.ticks.get() + 1;
+		self.ticks.set(ticks);
+		let due = {
+			let mut tick_timers = self.tick_timers.borrow_mut();
+			let later = tick_timers.split_off(&(ticks + 1, 0));
+			mem::replace(&mut *tick_timers, later)
+		};
+		self.timers.borrow_mut().extend(due.into_values());
+		let now = self.now();
+		loop {
+			let timer = {
+				let mut timers = self.timers.borrow_mut();
+				match timers.peek() {
+					Some(timer) if timer.due <= now => timers.pop(),
+					_ => None,
+				}
+			};
+			match timer {
+				Some(timer) if timer.sequence >= tick.sequence || timer.is_pending_once() => {
+					tick.deferred.push(timer);
+				}
+				Some(timer) => tick.fire(timer),
+				None => break,
+			}
+		}
+	}
+
+	/// The time the next closure is due, if any, so the caller can wait until then to `tick`.
+	///
+	/// The closures waiting for a tick, see `on_tick`, are not due at a time.
+	pub fn next_due(&self) -> Option<Duration> {
+		let mut removed = Vec::new();
+		let mut timers = self.timers.borrow_mut();
+		while timers.peek().is_some_and(|timer| !timer.subscription.is_connected()) {
+			removed.extend(timers.pop());
+		}
+		let due = timers.peek().map(|timer| timer.due);
+		// A closure may own the `Subscription` of another timer, which is only safe to drop once
+		// the heap is released.
+		drop(timers);
+		drop(removed);
+		due
+	}
+
+	/// Removes the cancelled timers, so the heap does not grow with the timers which are added
+	/// and cancelled before they are due, like the ones restarted on every event.
+	///
+	/// The timers waiting for a tick are removed too.
+	fn compact(&self) {
+		let removed: Vec<_> = {
+			let mut timers = self.timers.borrow_mut();
+			let (kept, mut removed): (Vec<_>, Vec<_>) =
+				mem::take(&mut *timers).into_vec().into_iter()
+				.partition(|timer| timer.subscription.is_connected());
+			*timers = BinaryHeap::from(kept);
+			let mut tick_timers = self.tick_timers.borrow_mut();
+			let (kept, tick_removed): (BTreeMap<_, _>, BTreeMap<_, _>) =
+				mem::take(&mut *tick_timers).into_iter()
+				.partition(|(_, timer)| timer.subscription.is_connected());
+			*tick_timers = kept;
+			removed.extend(tick_removed.into_values());
+			self.compacted_len.set(timers.len() + tick_timers.len());
+			removed
+		};
+		drop(removed);
+	}
+
+	/// Adds the timer due at `due`.
+	fn insert(&self, due: Duration, callback: TimerCallback) -> Subscription {
+		let (timer, subscription) = self.timer(due, callback);
+		self.timers.borrow_mut().push(timer);
+		subscription
+	}
+
+	/// Creates the timer due at `due` with its `Subscription`.
+	///
+	/// It calls `compact` when the timers reach twice the number the previous call left, so on
+	/// average each insertion pays a constant share of it.
+	fn timer(&self, due: Duration, callback: TimerCallback) -> (Timer, Subscription) {
+		let len = self.timers.borrow().len() + self.tick_timers.borrow().len();
+		if len >= 2 * self.compacted_len.get().max(1) {
+			self.compact();
+		}
+		let subscription = SubscriptionState::new();
+		let sequence = self.sequence.get();
+		self.sequence.set(sequence + 1);
+		let timer = Timer {
+			due,
+			sequence,
+			callback,
+			subscription: subscription.clone(),
+		};
+		(timer, Subscription::new(subscription))
+	}
+}
+
+/// A running `tick`, it holds the timers taken out of the scheduler which have to go back to it.
+///
+/// They are put back when it is dropped, even if one of the closures panics.
+struct Tick<'a> {
+	scheduler: &'a Scheduler,
+	/// The timers added before the tick started are the only ones it fires.
+	sequence: u64,
+	/// The timers added during the tick and the blocked one-shot ones, they are checked again on
+	/// the next tick.
+	deferred: Vec<Timer>,
+	/// The timer whose closure is running.
+	firing: Option<Timer>,
+}
+
+impl<'a> Tick<'a> {
+	/// Starts the tick, unless the `scheduler` is already running one.
+	fn start(scheduler: &'a Scheduler) -> Option<Self> {
+		if scheduler.ticking.replace(true) {
+			return None;
+		}
+		Some(Self {
+			scheduler,
+			sequence: scheduler.sequence.get(),
+			deferred: Vec::new(),
+			firing: None,
+		})
+	}
+
+	/// Fires the `timer` unless it was cancelled, an interval goes back to the scheduler with the
+	/// time it is due again.
+	fn fire(&mut self, timer: Timer) {
+		if !timer.subscription.is_connected() {
+			return;
+		}
+		let timer = self.firing.insert(timer);
+		match &mut timer.callback {
+			TimerCallback::Once(callback) => {
+				let callback = callback.take();
+				timer.subscription.disconnect();
+				if let Some(callback) = callback {
+					callback();
+				}
+			}
+			TimerCallback::Repeated(callback, interval) => {
+				timer.due += *interval;
+				if !timer.subscription.is_blocked() {
+					callback();
+				}
+			}
+		}
+		self.put_back();
+	}
+
+	/// Puts the timer which was firing back in the scheduler, unless it is done.
+	fn put_back(&mut self) {
+		if let Some(timer) = self.firing.take() {
+			if timer.subscription.is_connected() {
+				self.scheduler.timers.borrow_mut().push(timer);
+			}
+		}
+	}
+}
+
+impl<'a> Drop for Tick<'a> {
+	fn drop(&mut self) {
+		self.put_back();
+		self.scheduler.timers.borrow_mut().extend(self.deferred.drain(..));
+		self.scheduler.ticking.set(false);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::clock::{Clock, ManualClock};
+	use crate::scheduler::Scheduler;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+	use std::time::Duration;
+
+	fn millis(millis: u64) -> Duration {
+		Duration::from_millis(millis)
+	}
+
+	#[test]
+	fn delays_and_intervals() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Scheduler::new(clock.clone());
+		let fired = Rc::new(RefCell::new(Vec::new()));
+
+		let after_fired = fired.clone();
+		let _after_handler = scheduler.after(millis(150), Box::new(move ||
+			after_fired.borrow_mut().push("after".to_string())
+		));
+		let every_fired = fired.clone();
+		let clock_now = clock.clone();
+		let _every_handler = scheduler.every(millis(100), Box::new(move ||
+			every_fired.borrow_mut().push(format!("every {:?}", clock_now.now()))
+		));
+		let at_fired = fired.clone();
+		let _at_handler = scheduler.at(millis(250), Box::new(move ||
+			at_fired.borrow_mut().push("at".to_string())
+		));
+		scheduler.after(millis(10), Box::new(|| panic!()));
+		assert_eq!(Some(millis(100)), scheduler.next_due());
+
+		scheduler.tick();
+		assert!(fired.borrow().is_empty());
+		clock.advance(millis(160));
+		scheduler.tick();
+		clock.advance(millis(140));
+		scheduler.tick();
+		assert_eq!(
+			vec!["every 160ms", "after", "every 300ms", "at", "every 300ms"],
+			*fired.borrow());
+		assert_eq!(Some(millis(400)), scheduler.next_due());
+	}
+
+	#[test]
+	fn cancel_and_block() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Scheduler::new(clock.clone());
+		let fired = Rc::new(RefCell::new(Vec::new()));
+
+		let every_fired = fired.clone();
+		let every_handler = scheduler.every(millis(10), Box::new(move ||
+			every_fired.borrow_mut().push("every".to_string())
+		));
+		let after_fired = fired.clone();
+		let after_handler = scheduler.after(millis(10), Box::new(move ||
+			after_fired.borrow_mut().push("after".to_string())
+		));
+
+		let every_blocker = every_handler.block();
+		let after_blocker = after_handler.block();
+		clock.advance(millis(10));
+		scheduler.tick();
+		assert!(fired.borrow().is_empty());
+
+		drop(every_blocker);
+		drop(after_blocker);
+		clock.advance(millis(10));
+		scheduler.tick();
+		assert_eq!(vec!["after", "every"], *fired.borrow());
+		assert!(!after_handler.is_connected());
+
+		drop(every_handler);
+		clock.advance(millis(10));
+		scheduler.tick();
+		assert_eq!(2, fired.borrow().len());
+		assert_eq!(None, scheduler.next_due());
+	}
+
+	#[test]
+	fn schedule_from_callback() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Rc::new(Scheduler::new(clock.clone()));
+		let fired = Rc::new(RefCell::new(Vec::new()));
+
+		let inner_scheduler = Rc::downgrade(&scheduler);
+		let handlers = Rc::new(RefCell::new(Vec::new()));
+		let inner_handlers = handlers.clone();
+		let after_fired = fired.clone();
+		let _after_handler = scheduler.after(millis(10), Box::new(move || {
+			after_fired.borrow_mut().push("first".to_string());
+			let scheduler = inner_scheduler.upgrade().unwrap();
+			let nested_fired = after_fired.clone();
+			inner_handlers.borrow_mut().push(scheduler.after(Duration::ZERO, Box::new(move ||
+				nested_fired.borrow_mut().push("nested".to_string())
+			)));
+			scheduler.tick();
+		}));
+
+		clock.advance(millis(10));
+		scheduler.tick();
+		assert_eq!(vec!["first"], *fired.borrow());
+		scheduler.tick();
+		assert_eq!(vec!["first", "nested"], *fired.borrow());
+	}
+
+	#[test]
+	fn cancelled_timers_are_removed() {
+		let scheduler = Scheduler::new(Rc::new(ManualClock::new()));
+
+		let _kept_handler = scheduler.after(millis(10), Box::new(|| {}));
+		for _ in 0..1000 {
+			drop(scheduler.after(millis(10), Box::new(|| panic!())));
+			assert!(scheduler.timers.borrow().len() <= 2);
+		}
+		assert_eq!(Some(millis(10)), scheduler.next_due());
+	}
+
+	#[test]
+	fn tick_numbers() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Scheduler::new(clock.clone());
+		let fired = Rc::new(RefCell::new(Vec::new()));
+
+		let after_fired = fired.clone();
+		let _after_handler = scheduler.after(millis(10), Box::new(move ||
+			after_fired.borrow_mut().push("after".to_string())
+		));
+		let second_fired = fired.clone();
+		let _second_handler = scheduler.on_tick(2, Box::new(move ||
+			second_fired.borrow_mut().push("second".to_string())
+		));
+		let first_fired = fired.clone();
+		let _first_handler = scheduler.after_ticks(1, Box::new(move ||
+			first_fired.borrow_mut().push("first".to_string())
+		));
+		let cancelled_handler = scheduler.on_tick(2, Box::new(|| panic!()));
+		drop(cancelled_handler);
+
+		clock.advance(millis(10));
+		scheduler.tick();
+		assert_eq!(1, scheduler.ticks());
+		assert_eq!(vec!["first", "after"], *fired.borrow());
+
+		let blocked_fired = fired.clone();
+		let blocked_handler = scheduler.on_tick(1, Box::new(move ||
+			blocked_fired.borrow_mut().push("blocked".to_string())
+		));
+		let blocker = blocked_handler.block();
+		scheduler.tick();
+		assert_eq!(vec!["first", "after", "second"], *fired.borrow());
+
+		drop(blocker);
+		scheduler.tick();
+		assert_eq!(3, scheduler.ticks());
+		assert_eq!(vec!["first", "after", "second", "blocked"], *fired.borrow());
+		assert!(!blocked_handler.is_connected());
+		assert_eq!(None, scheduler.next_due());
+	}
+
+	#[test]
+	fn tick_after_panic() {
+		let clock = Rc::new(ManualClock::new());
+		let scheduler = Scheduler::new(clock.clone());
+		let fired = Rc::new(RefCell::new(Vec::new()));
+
+		let _panic_handler = scheduler.after(millis(10), Box::new(|| panic!()));
+		let every_fired = fired.clone();
+		let _every_handler = scheduler.every(millis(10), Box::new(move || {
+			every_fired.borrow_mut().push("every".to_string());
+			assert_eq!(1, every_fired.borrow().len());
+		}));
+		let after_fired = fired.clone();
+		let after_handler = scheduler.after(millis(20), Box::new(move ||
+			after_fired.borrow_mut().push("after".to_string())
+		));
+		let blocked_fired = fired.clone();
+		let blocked_handler = scheduler.after(millis(5), Box::new(move ||
+			blocked_fired.borrow_mut().push("blocked".to_string())
+		));
+		let blocker = blocked_handler.block();
+
+		clock.advance(millis(10));
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| scheduler.tick()));
+		assert!(result.is_err());
+		assert!(blocked_handler.is_connected());
+
+		clock.advance(millis(10));
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| scheduler.tick()));
+		assert!(result.is_err());
+		drop(blocker);
+		scheduler.tick();
+		assert_eq!(vec!["every", "every", "blocked", "after"], *fired.borrow());
+		assert!(!after_handler.is_connected());
+		assert_eq!(Some(millis(30)), scheduler.next_due());
+	}
+}
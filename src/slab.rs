@@ -0,0 +1,136 @@
+/// Identifies a value stored in a `Slab`.
+///
+/// It carries the generation of its slot, so the key of a removed value does not give access to
+/// the value stored later in the same slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SlabKey {
+	index: usize,
+	generation: u32,
+}
+
+/// A position of the slab, the generation changes every time its value is removed.
+struct Slot<T> {
+	generation: u32,
+	value: Option<T>,
+}
+
+/// Slab stores values in a vector reusing the slots of the removed ones.
+///
+/// Inserting, removing and accessing a value take constant time and the values never move while
+/// they are in the slab.
+pub struct Slab<T> {
+	slots: Vec<Slot<T>>,
+	/// Indexes of the empty slots.
+	free: Vec<usize>,
+	len: usize,
+}
+
+/// An empty slab, whatever the type of its values.
+impl<T> Default for Slab<T> {
+	fn default() -> Self {
+		Self {
+			slots: Vec::new(),
+			free: Vec::new(),
+			len: 0,
+		}
+	}
+}
+
+impl<T> Slab<T> {
+	/// Stores the value returning its key.
+	pub fn insert(&mut self, value: T) -> SlabKey {
+		self.len += 1;
+		if let Some(index) = self.free.pop() {
+			let slot = &mut self.slots[index];
+			slot.value = Some(value);
+			return SlabKey {
+				index,
+				generation: slot.generation,
+			};
+		}
+		self.slots.push(Slot {
+			generation: 0,
+			value: Some(value),
+		});
+		SlabKey {
+			index: self.slots.len() - 1,
+			generation: 0,
+		}
+	}
+
+	/// Removes the value of the `key`, it returns `None` if it was already removed.
+	pub fn remove(&mut self, key: SlabKey) -> Option<T> {
+		let slot = self.slots.get_mut(key.index)
+			.filter(|slot| slot.generation == key.generation)?;
+		let value = slot.value.take()?;
+		slot.generation = slot.generation.wrapping_add(1);
+		self.free.push(key.index);
+		self.len -= 1;
+		Some(value)
+	}
+
+	/// The value of the `key`, if it was not removed.
+	pub fn get(&self, key: SlabKey) -> Option<&T> {
+		self.slots.get(key.index)
+			.filter(|slot| slot.generation == key.generation)
+			.and_then(|slot| slot.value.as_ref())
+	}
+
+	/// The mutable counterpart of `get`.
+	pub fn get_mut(&mut self, key: SlabKey) -> Option<&mut T> {
+		self.slots.get_mut(key.index)
+			.filter(|slot| slot.generation == key.generation)
+			.and_then(|slot| slot.value.as_mut())
+	}
+
+	/// Indicates if the value of the `key` is still in the slab.
+	pub fn contains(&self, key: SlabKey) -> bool {
+		self.get(key).is_some()
+	}
+
+	/// The number of values in the slab.
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Indicates if there are no values in the slab.
+	pub fn is_empty(&self) -> bool {
+		self.len == 0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::slab::Slab;
+
+	#[test]
+	fn insert_and_remove() {
+		let mut slab = Slab::default();
+		let first = slab.insert("first");
+		let second = slab.insert("second");
+		assert_eq!(2, slab.len());
+		assert_eq!(Some(&"second"), slab.get(second));
+
+		assert_eq!(Some("first"), slab.remove(first));
+		assert_eq!(None, slab.remove(first));
+		assert!(!slab.contains(first));
+		assert_eq!(1, slab.len());
+
+		*slab.get_mut(second).unwrap() = "changed";
+		assert_eq!(Some("changed"), slab.remove(second));
+		assert!(slab.is_empty());
+	}
+
+	#[test]
+	fn reused_slot() {
+		let mut slab = Slab::default();
+		let first = slab.insert(1);
+		slab.remove(first);
+		let second = slab.insert(2);
+
+		assert_ne!(first, second);
+		assert_eq!(None, slab.get(first));
+		assert_eq!(None, slab.remove(first));
+		assert_eq!(Some(&2), slab.get(second));
+	}
+}
@@ -0,0 +1,419 @@
+use crate::slab::SlabKey;
+use std::cell::{Cell, RefCell};
+use std::panic::Location;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Identifies a subscription, it is unique for the whole program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubscriptionId(u64);
+
+impl SubscriptionId {
+	/// Creates a new unique identifier.
+	fn next() -> Self {
+		static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+		Self(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+	}
+}
+
+/// The keys of the disconnected callbacks waiting to be removed from their registry.
+pub(crate) type Disconnected = RefCell<Vec<SlabKey>>;
+
+/// State shared between a `Subscription` and the registry entry of its callback.
+#[derive(Debug)]
+pub(crate) struct SubscriptionState {
+	id: SubscriptionId,
+	connected: Cell<bool>,
+	/// Number of `SubscriptionBlocker` alive for the subscription.
+	blocked: Cell<usize>,
+	/// Where the key of the callback is sent once it is disconnected, so the registry can remove
+	/// it without going through all its callbacks.
+	registry: RefCell<Option<(Weak<Disconnected>, SlabKey)>>,
+	/// Optional name used to identify the callback when debugging.
+	label: RefCell<Option<String>>,
+}
+
+impl SubscriptionState {
+	/// Creates the state for a new callback.
+	pub(crate) fn new() -> Rc<Self> {
+		Rc::new(Self {
+			id: SubscriptionId::next(),
+			connected: Cell::new(true),
+			blocked: Cell::new(0),
+			registry: RefCell::new(None),
+			label: RefCell::new(None),
+		})
+	}
+
+	/// The identifier of the subscription.
+	pub(crate) fn id(&self) -> SubscriptionId {
+		self.id
+	}
+
+	/// Indicates if the callback should still be kept by the registry.
+	pub(crate) fn is_connected(&self) -> bool {
+		self.connected.get()
+	}
+
+	/// The label of the callback, if any.
+	pub(crate) fn label(&self) -> Option<String> {
+		self.label.borrow().clone()
+	}
+
+	/// Indicates if the callback should be skipped by the dispatch, without being removed.
+	pub(crate) fn is_blocked(&self) -> bool {
+		self.blocked.get() > 0
+	}
+
+	/// Marks the callback to be removed by the registry, the registry also calls it when the
+	/// callback is dropped.
+	pub(crate) fn disconnect(&self) {
+		if !self.connected.replace(false) {
+			return;
+		}
+		let registry = self.registry.borrow_mut().take();
+		if let Some((disconnected, key)) = registry {
+			if let Some(disconnected) = disconnected.upgrade() {
+				disconnected.borrow_mut().push(key);
+			}
+		}
+	}
+
+	/// Sets the registry which stored the callback with the `key`.
+	pub(crate) fn set_registry(&self, disconnected: &Rc<Disconnected>, key: SlabKey) {
+		self.registry.replace(Some((Rc::downgrade(disconnected), key)));
+	}
+}
+
+/// Handler returned when a callback is added to a registry.
+///
+/// The callback is disconnected once the `Subscription` is dropped, unless it was detached.
+/// It does not implement `Clone` on purpose, so there is only one owner deciding when the
+/// callback goes away.
+#[derive(Debug)]
+pub struct Subscription {
+	state: Rc<SubscriptionState>,
+	detached: bool,
+}
+
+impl Subscription {
+	/// Creates the subscription for the state shared with a registry entry.
+	pub(crate) fn new(state: Rc<SubscriptionState>) -> Self {
+		Self {
+			state,
+			detached: false,
+		}
+	}
+
+	/// The state shared with the registry entry of the callback.
+	pub(crate) fn state(&self) -> &Rc<SubscriptionState> {
+		&self.state
+	}
+
+	/// The identifier of the subscription, it is used by the registries to report on the callback.
+	pub fn id(&self) -> SubscriptionId {
+		self.state.id()
+	}
+
+	/// Sets a label to identify the callback when inspecting the registry, it is meant to be used
+	/// right after adding the callback:
+	/// `let subscription = registry.add(callback).with_label("minimap");`
+	pub fn with_label(self, label: &str) -> Self {
+		self.state.label.replace(Some(label.to_string()));
+		self
+	}
+
+	/// The label of the callback, see `with_label`.
+	pub fn label(&self) -> Option<String> {
+		self.state.label()
+	}
+
+	/// Disconnects the callback, it is not going to be executed anymore.
+	pub fn disconnect(&self) {
+		self.state.disconnect();
+	}
+
+	/// Indicates if the callback can still be executed.
+	///
+	/// It is `false` after `disconnect`, after a one-shot callback ran or once the registry is
+	/// dropped.
+	pub fn is_connected(&self) -> bool {
+		self.state.is_connected()
+	}
+
+	/// Temporarily suppresses the callback without removing it from the registry.
+	/// The callback is blocked while the returned `SubscriptionBlocker` is alive.
+	pub fn block(&self) -> SubscriptionBlocker {
+		self.state.blocked.set(self.state.blocked.get() + 1);
+		SubscriptionBlocker {
+			state: self.state.clone(),
+		}
+	}
+
+	/// Indicates if there is a `SubscriptionBlocker` alive for the callback.
+	pub fn is_blocked(&self) -> bool {
+		self.state.is_blocked()
+	}
+
+	/// Drops the subscription keeping the callback connected for the lifetime of the registry.
+	pub fn detach(mut self) {
+		self.detached = true;
+	}
+}
+
+impl Drop for Subscription {
+	fn drop(&mut self) {
+		if !self.detached {
+			self.disconnect();
+		}
+	}
+}
+
+/// Description of a callback connected to a registry, returned by
+/// `CallbackRegistry::subscriptions`.
+#[derive(Clone, Debug)]
+pub struct SubscriptionInfo {
+	id: SubscriptionId,
+	label: Option<String>,
+	priority: i32,
+	location: &'static Location<'static>,
+	blocked: bool,
+}
+
+impl SubscriptionInfo {
+	/// Creates the description of the callback with the `state`.
+	pub(crate) fn new(
+		state: &SubscriptionState, priority: i32, location: &'static Location<'static>,
+	) -> Self {
+		Self {
+			id: state.id(),
+			label: state.label(),
+			priority,
+			location,
+			blocked: state.is_blocked(),
+		}
+	}
+
+	/// The identifier of the subscription.
+	pub fn id(&self) -> SubscriptionId {
+		self.id
+	}
+
+	/// The label of the subscription, see `Subscription::with_label`.
+	pub fn label(&self) -> Option<&str> {
+		self.label.as_deref()
+	}
+
+	/// The priority the callback was added with.
+	pub fn priority(&self) -> i32 {
+		self.priority
+	}
+
+	/// Where the callback was added.
+	pub fn location(&self) -> &'static Location<'static> {
+		self.location
+	}
+
+	/// Indicates if the callback was blocked at the moment the description was made.
+	pub fn is_blocked(&self) -> bool {
+		self.blocked
+	}
+}
+
+/// Guard returned by `Subscription::block`, the callback is unblocked when it is dropped.
+#[derive(Debug)]
+pub struct SubscriptionBlocker {
+	state: Rc<SubscriptionState>,
+}
+
+impl SubscriptionBlocker {
+	/// Unblocks the callback, the same as dropping the guard.
+	pub fn unblock(self) {}
+}
+
+impl Drop for SubscriptionBlocker {
+	fn drop(&mut self) {
+		self.state.blocked.set(self.state.blocked.get() - 1);
+	}
+}
+
+/// Bag of subscriptions, possibly from many registries, which are disconnected together.
+///
+/// All the callbacks are disconnected when the group is dropped or when `clear` is called, so an
+/// object can keep a single group as field instead of one field for each subscription.
+#[derive(Debug, Default)]
+pub struct SubscriptionGroup {
+	subscriptions: Vec<Subscription>,
+}
+
+impl SubscriptionGroup {
+	/// Adds the `subscription` to the group, it is disconnected together with the others.
+	pub fn add(&mut self, subscription: Subscription) {
+		self.subscriptions.push(subscription);
+	}
+
+	/// Disconnects all the subscriptions of the group, the group can be reused after that.
+	pub fn clear(&mut self) {
+		for subscription in self.subscriptions.drain(..) {
+			subscription.disconnect();
+		}
+	}
+
+	/// The number of subscriptions in the group.
+	pub fn len(&self) -> usize {
+		self.subscriptions.len()
+	}
+
+	/// Indicates if the group has no subscriptions.
+	pub fn is_empty(&self) -> bool {
+		self.subscriptions.is_empty()
+	}
+}
+
+impl Extend<Subscription> for SubscriptionGroup {
+	fn extend<I: IntoIterator<Item=Subscription>>(&mut self, iter: I) {
+		self.subscriptions.extend(iter);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::callback::{
+		CallbackManager,
+		CallbackRegistry,
+	};
+	use crate::subscription::SubscriptionGroup;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[test]
+	fn disconnect() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let subscription = my_callback.add(Box::new(move |x| first_received.borrow_mut().push(x)));
+		assert!(subscription.is_connected());
+
+		my_callback.run_all(1);
+		subscription.disconnect();
+		assert!(!subscription.is_connected());
+		my_callback.run_all(2);
+		assert_eq!(vec![1], *received.borrow());
+	}
+
+	#[test]
+	fn block_and_unblock() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let subscription = my_callback.add(Box::new(move |x| first_received.borrow_mut().push(x)));
+
+		let first_blocker = subscription.block();
+		let second_blocker = subscription.block();
+		assert!(subscription.is_blocked());
+		assert!(subscription.is_connected());
+		my_callback.run_all(1);
+
+		first_blocker.unblock();
+		assert!(subscription.is_blocked());
+		my_callback.run_all(2);
+
+		drop(second_blocker);
+		assert!(!subscription.is_blocked());
+		my_callback.run_all(3);
+		assert_eq!(vec![3], *received.borrow());
+	}
+
+	#[test]
+	fn blocked_once_callback_stays_pending() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let once_received = received.clone();
+		let subscription = my_callback.add_once(
+			Box::new(move |x| once_received.borrow_mut().push(x)));
+
+		let blocker = subscription.block();
+		my_callback.run_all(1);
+		assert!(subscription.is_connected());
+
+		drop(blocker);
+		my_callback.run_all(2);
+		my_callback.run_all(3);
+		assert!(!subscription.is_connected());
+		assert_eq!(vec![2], *received.borrow());
+	}
+
+	#[test]
+	fn detach() {
+		let my_callback: Rc<CallbackRegistry<u32>> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		my_callback.add(Box::new(move |x| first_received.borrow_mut().push(x))).detach();
+
+		my_callback.run_all(1);
+		my_callback.run_all(2);
+		assert_eq!(vec![1, 2], *received.borrow());
+	}
+
+	#[test]
+	fn registry_dropped() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+
+		let subscription = my_callback.add(Box::new(|_| {}));
+		assert!(subscription.is_connected());
+
+		let blocker = subscription.block();
+		drop(my_callback);
+		assert!(!subscription.is_connected());
+		drop(blocker);
+	}
+
+	#[test]
+	fn group_from_many_registries() {
+		let first_registry: CallbackRegistry<u32> = Default::default();
+		let second_registry: CallbackManager = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+		let mut group = SubscriptionGroup::default();
+		assert!(group.is_empty());
+
+		let first_received = received.clone();
+		group.add(first_registry.add(Box::new(move |x| first_received.borrow_mut().push(x))));
+		let second_received = received.clone();
+		let third_received = received.clone();
+		group.extend(vec![
+			second_registry.add(Box::new(move || second_received.borrow_mut().push(0))),
+			first_registry.add(Box::new(move |x| third_received.borrow_mut().push(x * 10))),
+		]);
+		assert_eq!(3, group.len());
+
+		first_registry.run_all(1);
+		second_registry.run_all();
+		assert_eq!(vec![1, 10, 0], *received.borrow());
+
+		group.clear();
+		assert!(group.is_empty());
+		first_registry.run_all(2);
+		second_registry.run_all();
+		assert_eq!(vec![1, 10, 0], *received.borrow());
+	}
+
+	#[test]
+	fn group_dropped() {
+		let my_callback: CallbackRegistry<u32> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		{
+			let mut group = SubscriptionGroup::default();
+			let first_received = received.clone();
+			group.add(my_callback.add(Box::new(move |x| first_received.borrow_mut().push(x))));
+			my_callback.run_all(1);
+		}
+
+		my_callback.run_all(2);
+		assert_eq!(vec![1], *received.borrow());
+	}
+}
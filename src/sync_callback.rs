@@ -0,0 +1,164 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+
+/// Wrapper for the thread-safe callback handler.
+///
+/// It plays the role of the `subscription::Subscription` returned by `callback::CallbackRegistry`,
+/// but it is a plain `Arc<()>` on purpose: `Subscription` shares its state through a `Rc`, so it
+/// can not leave its thread, while this handler can be moved to and dropped from any thread.
+///
+/// The registry only keeps a `Weak` reference to it, so once every `Arc` is dropped the closure
+/// is considered unsubscribed.
+pub type SyncCallbackHandler = Arc<()>;
+
+/// The closures are stored behind an `Arc` so they can be cloned out of the lock before running.
+type SyncCallback<ParamType> = Arc<dyn Fn(ParamType) + Send + Sync>;
+
+/// Thread-safe version of `callback::CallbackManager`.
+#[derive(Default)]
+pub struct SyncCallbackManager {
+	manager: SyncCallbackRegistry<()>,
+}
+
+impl SyncCallbackManager {
+	/// The `add` method accepts a closure with no arguments and returns an handler.
+	/// Once the handler is dropped the closure will not be executed anymore.
+	pub fn add(&self, callback: Box<dyn Fn() + Send + Sync>) -> SyncCallbackHandler {
+		self.manager.add(Box::new(move |_| callback()))
+	}
+
+	/// The `run_all` method runs all the closures if their handler was not dropped it.
+	pub fn run_all(&self) {
+		self.manager.run_all(())
+	}
+}
+
+/// Thread-safe version of `callback::CallbackRegistry`.
+///
+/// All the methods take `&self`, so the registry can be shared with an `Arc` and closures can be
+/// added or fired from any thread.
+/// The lock is only held while the list of callbacks is being updated, the closures themselves
+/// run without it, which means a callback can add new callbacks to the same registry.
+pub struct SyncCallbackRegistry<ParamType: Copy> {
+	callbacks: Mutex<Vec<(SyncCallback<ParamType>, Weak<()>)>>,
+}
+
+impl<ParamType: Copy> Default for SyncCallbackRegistry<ParamType> {
+	fn default() -> Self {
+		Self {
+			callbacks: Mutex::new(Vec::new()),
+		}
+	}
+}
+
+impl<ParamType: Copy> SyncCallbackRegistry<ParamType> {
+	/// The `add` method accepts a closure with with a parameter and returns an handler.
+	/// Once the handler is dropped the closure will not be executed anymore.
+	pub fn add(&self, callback: Box<dyn Fn(ParamType) + Send + Sync>) -> SyncCallbackHandler {
+		let resp = Arc::new(());
+		self.lock().push((Arc::from(callback), Arc::downgrade(&resp)));
+		resp
+	}
+
+	/// The `run_all` method runs all the closures if their handler was not dropped it.
+	///
+	/// The callbacks are copied out of the lock before running, the handler is checked again right
+	/// before each call so a handler dropped by another thread during the dispatch is respected.
+	pub fn run_all(&self, param: ParamType) {
+		let callbacks: Vec<_> = {
+			let mut callbacks = self.lock();
+			callbacks.retain(|(_, handler)| handler.strong_count() > 0);
+			callbacks.clone()
+		};
+		for (callback, handler) in callbacks {
+			if handler.strong_count() > 0 {
+				callback(param);
+			}
+		}
+	}
+
+	/// A panicking callback never holds the lock, so a poisoned lock still has a consistent list.
+	fn lock(&self) -> MutexGuard<'_, Vec<(SyncCallback<ParamType>, Weak<()>)>> {
+		self.callbacks.lock().unwrap_or_else(PoisonError::into_inner)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::sync_callback::{
+		SyncCallbackManager,
+		SyncCallbackRegistry,
+	};
+	use std::sync::{Arc, Mutex};
+	use std::sync::atomic::{AtomicU32, Ordering};
+	use std::thread;
+
+	#[test]
+	fn no_param_callback() {
+		let my_callback: SyncCallbackManager = Default::default();
+		let counter = Arc::new(AtomicU32::new(0));
+
+		let first_counter = counter.clone();
+		let _first_handler = my_callback.add(Box::new(move || {
+			first_counter.fetch_add(1, Ordering::SeqCst);
+		}));
+		my_callback.add(Box::new(|| {
+			println!("Dropped sync callback no param");
+			panic!();
+		}));
+
+		my_callback.run_all();
+		my_callback.run_all();
+		assert_eq!(2, counter.load(Ordering::SeqCst));
+	}
+
+	#[test]
+	fn u32_param_callback_across_threads() {
+		let my_callback: Arc<SyncCallbackRegistry<u32>> = Default::default();
+		let sum = Arc::new(AtomicU32::new(0));
+
+		let handlers: Vec<_> = (0..4).map(|_| {
+			let my_callback = my_callback.clone();
+			let sum = sum.clone();
+			thread::spawn(move || my_callback.add(Box::new(move |x| {
+				sum.fetch_add(x, Ordering::SeqCst);
+			}))).join().unwrap()
+		}).collect();
+
+		let firing: Vec<_> = (1..=3).map(|x| {
+			let my_callback = my_callback.clone();
+			thread::spawn(move || my_callback.run_all(x))
+		}).collect();
+		for it in firing {
+			it.join().unwrap();
+		}
+		assert_eq!(4 * (1 + 2 + 3), sum.load(Ordering::SeqCst));
+
+		drop(handlers);
+		my_callback.run_all(100);
+		assert_eq!(4 * (1 + 2 + 3), sum.load(Ordering::SeqCst));
+	}
+
+	#[test]
+	fn add_from_callback() {
+		let my_callback: Arc<SyncCallbackRegistry<u32>> = Default::default();
+		let counter = Arc::new(AtomicU32::new(0));
+		let added_handlers = Arc::new(Mutex::new(Vec::new()));
+
+		let inner_registry = Arc::downgrade(&my_callback);
+		let inner_counter = counter.clone();
+		let inner_handlers = added_handlers.clone();
+		let _handler = my_callback.add(Box::new(move |_| {
+			let inner_counter = inner_counter.clone();
+			let registry = inner_registry.upgrade().unwrap();
+			inner_handlers.lock().unwrap().push(registry.add(Box::new(move |x| {
+				inner_counter.fetch_add(x, Ordering::SeqCst);
+			})));
+		}));
+
+		my_callback.run_all(1);
+		assert_eq!(0, counter.load(Ordering::SeqCst));
+		my_callback.run_all(1);
+		assert_eq!(1, counter.load(Ordering::SeqCst));
+		assert_eq!(2, added_handlers.lock().unwrap().len());
+	}
+}
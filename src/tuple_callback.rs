@@ -0,0 +1,124 @@
+use crate::callback::CallbackRegistry;
+use crate::subscription::Subscription;
+
+/// A closure taking the elements of a tuple as separate arguments.
+///
+/// The registry stores the closures behind this trait instead of wrapping them in a closure taking
+/// the tuple, the wrapper would have to be `'static`, so would the elements of the tuple.
+pub(crate) trait ArgsCallback<ParamType, ReturnType> {
+	/// Runs the closure with a copy of each element of the `param`.
+	fn call(&mut self, param: &ParamType) -> ReturnType;
+}
+
+/// Implements the methods taking the arguments separately for a `CallbackRegistry` whose
+/// parameter is a tuple with the given arity.
+///
+/// Each argument is cloned for each closure, the same way `CallbackRegistry::add` clones the
+/// parameter.
+macro_rules! impl_tuple_callback {
+	($($name:ident: $type:ident),*) => {
+		impl<$($type: Clone,)* ReturnType, Closure: FnMut($($type),*) -> ReturnType>
+			ArgsCallback<($($type,)*), ReturnType> for Closure {
+			fn call(&mut self, ($($name,)*): &($($type,)*)) -> ReturnType {
+				self($($name.clone()),*)
+			}
+		}
+
+		impl<$($type: Clone,)* ReturnType> CallbackRegistry<($($type,)*), ReturnType> {
+			/// The `add_args` method accepts a closure taking each element of the tuple as a
+			/// separate argument, see `CallbackRegistry::add`.
+			///
+			/// The closure is generic instead of a `dyn FnMut`, so the elements of the tuple can
+			/// borrow something, only the closure has to be `'static`.
+			#[track_caller]
+			pub fn add_args<Closure: FnMut($($type),*) -> ReturnType + 'static>(
+				&self, callback: Box<Closure>,
+			) -> Subscription {
+				self.insert_args(callback)
+			}
+
+			/// The `run_all_args` method runs all the closures receiving the arguments separately,
+			/// see `CallbackRegistry::run_all`.
+			// The number of arguments is the arity of the tuple.
+			#[allow(clippy::too_many_arguments)]
+			pub fn run_all_args(&self, $($name: $type),*) {
+				self.run_all(($($name,)*))
+			}
+		}
+	};
+}
+
+impl_tuple_callback!();
+impl_tuple_callback!(a: A);
+impl_tuple_callback!(a: A, b: B);
+impl_tuple_callback!(a: A, b: B, c: C);
+impl_tuple_callback!(a: A, b: B, c: C, d: D);
+impl_tuple_callback!(a: A, b: B, c: C, d: D, e: E);
+impl_tuple_callback!(a: A, b: B, c: C, d: D, e: E, f: F);
+impl_tuple_callback!(a: A, b: B, c: C, d: D, e: E, f: F, g: G);
+impl_tuple_callback!(a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H);
+
+#[cfg(test)]
+mod tests {
+	use crate::callback::CallbackRegistry;
+	use crate::combiner::Collect;
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	#[derive(Clone, Copy, Debug, PartialEq)]
+	struct Point {
+		x: i32,
+		y: i32,
+	}
+
+	#[test]
+	fn three_args_callback() {
+		let first = String::from("first");
+		let second = String::from("second");
+		let my_callback: CallbackRegistry<(u32, &str, Point)> = Default::default();
+		let received = Rc::new(RefCell::new(Vec::new()));
+
+		let first_received = received.clone();
+		let _first_handler = my_callback.add_args(Box::new(move |id, name, point|
+			first_received.borrow_mut().push(format!("{} {} {:?}", id, name, point))
+		));
+		let second_received = received.clone();
+		let _second_handler = my_callback.add(Box::new(move |(id, _, point)|
+			second_received.borrow_mut().push(format!("{} at {}", id, point.x + point.y))
+		));
+
+		my_callback.run_all_args(1, &first, Point { x: 1, y: 2 });
+		my_callback.run_all((2, &second, Point { x: 3, y: 4 }));
+		assert_eq!(
+			vec![
+				"1 first Point { x: 1, y: 2 }",
+				"1 at 3",
+				"2 second Point { x: 3, y: 4 }",
+				"2 at 7",
+			],
+			*received.borrow());
+	}
+
+	#[test]
+	fn args_with_return_values() {
+		let my_callback: CallbackRegistry<(i32, i32), i32> = Default::default();
+
+		let _sum_handler = my_callback.add_args(Box::new(|a, b| a + b));
+		let _product_handler = my_callback.add_args(Box::new(|a, b| a * b));
+
+		assert_eq!(vec![5, 6], my_callback.run_all_with((2, 3), Collect));
+	}
+
+	#[test]
+	fn no_args_callback() {
+		let my_callback: CallbackRegistry<()> = Default::default();
+		let calls = Rc::new(RefCell::new(0));
+
+		let first_calls = calls.clone();
+		let _first_handler = my_callback.add_args(Box::new(move || *first_calls.borrow_mut() += 1));
+
+		my_callback.run_all_args();
+		my_callback.run_all_args();
+		assert_eq!(2, *calls.borrow());
+	}
+}